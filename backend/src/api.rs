@@ -2,6 +2,11 @@ use anyhow::*;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    health::{LinkReport, OriginalStatus},
+    quota::UsageReport,
+};
+
 /// A collection of search entries.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(transparent)]
@@ -22,12 +27,165 @@ pub struct SearchEntry {
 }
 
 /// A entry in the vector database.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Entry {
     pub id: String,
     pub payload: serde_json::Value,
 }
 
+/// Who may see an entry, from the least to the most widely shared.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Only the user who added it and admins
+    Private,
+    /// Every authenticated user
+    #[default]
+    Team,
+    /// Every authenticated user, and anyone the archive is published to, as static sites only include
+    /// public entries by default
+    Public,
+}
+
+impl std::fmt::Display for Visibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Visibility::Private => "private",
+            Visibility::Team => "team",
+            Visibility::Public => "public",
+        })
+    }
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "private" => Ok(Visibility::Private),
+            "team" => Ok(Visibility::Team),
+            "public" => Ok(Visibility::Public),
+            _ => bail!("unknown visibility {s}, expected private, team or public"),
+        }
+    }
+}
+
+/// A link to add to the archive, the input data for the add link endpoint.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AddLink {
+    pub link: String,
+    pub description: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The name of a collection the entry belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    /// Unix timestamp in seconds of when the original post was made, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub posted_at: Option<u64>,
+}
+
+impl AddLink {
+    pub fn new(link: &str, description: &str) -> Self {
+        Self {
+            link: link.to_string(),
+            description: description.to_string(),
+            visibility: Visibility::default(),
+            tags: vec![],
+            collection: None,
+            posted_at: None,
+        }
+    }
+}
+
+/// Changes to an existing entry, fields that are `None` are left unchanged.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct EntryUpdate {
+    /// The token ID of the new owner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Changing the description embeds it again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+/// The outcome of refreshing an entry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshResult {
+    /// Whether the link's content differed, and the previous content was kept as a version
+    pub changed: bool,
+    pub entry: Entry,
+}
+
+/// The payload fields that describe an entry's content, as archived from its link.
+/// Refreshing an entry replaces them and keeps the previous ones as a version.
+pub const CONTENT_FIELDS: [&str; 8] = [
+    "cid",
+    "content_type",
+    "thumbnail",
+    "preview",
+    "media",
+    "sidecars",
+    "text",
+    "downloader",
+];
+
+/// A version of an entry's content
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntryVersion {
+    /// Counted from 1 for the first archived content, the highest is the current content
+    pub version: usize,
+    /// Unix timestamp in seconds of when this content was archived, if known
+    pub archived_at: Option<u64>,
+    pub current: bool,
+    /// The content fields of this version, see `CONTENT_FIELDS`
+    pub content: serde_json::Value,
+}
+
+impl EntryVersion {
+    /// The versions of an entry, oldest first. The previous versions are kept in the payload's `versions` list.
+    pub fn of_payload(payload: &serde_json::Value) -> Vec<Self> {
+        let previous = payload["versions"].as_array().cloned().unwrap_or_default();
+        let current = serde_json::Value::Object(
+            CONTENT_FIELDS
+                .iter()
+                .map(|field| (field.to_string(), payload[*field].clone()))
+                .collect(),
+        );
+        let archived_at = payload["refreshed_at"]
+            .as_u64()
+            .or(payload["added_at"].as_u64());
+
+        let count = previous.len() + 1;
+        previous
+            .into_iter()
+            .map(|mut version| {
+                let archived_at = version
+                    .as_object_mut()
+                    .and_then(|version| version.remove("archived_at"))
+                    .and_then(|archived_at| archived_at.as_u64());
+                (archived_at, version)
+            })
+            .chain([(archived_at, current)])
+            .enumerate()
+            .map(|(i, (archived_at, content))| Self {
+                version: i + 1,
+                archived_at,
+                current: i + 1 == count,
+                content,
+            })
+            .collect()
+    }
+}
+
 /// The top-level API of this project
 #[async_trait(?Send)]
 pub trait ClientApi: Send + Sync + 'static {
@@ -35,10 +193,35 @@ pub trait ClientApi: Send + Sync + 'static {
     ///
     /// Downloads the file, stores the file, generates embeddings for the description,
     /// submits to the vector database.
-    async fn add_link(&self, link: &str, description: &str) -> Result<Entry>;
+    async fn add_link(&self, input: &AddLink) -> Result<Entry>;
 
     /// Searches the vector database with the given description.
     ///
     /// Generates embeddings for the description and queries the vector database.
+    /// Only returns entries the user may see.
     async fn search(&self, description: &str) -> Result<SearchResult>;
+
+    /// Fetches an entry by its ID. Fails if the entry doesn't exist or the user may not see it.
+    async fn get_entry(&self, id: &str) -> Result<Entry>;
+
+    /// Changes the ownership, visibility, description, tags or collection of an entry.
+    ///
+    /// Only the owner of an entry and admins may change it, and only admins may reassign its owner.
+    async fn update_entry(&self, id: &str, update: &EntryUpdate) -> Result<Entry>;
+
+    /// Downloads the link of an entry again. If its content changed, the new content replaces the entry's
+    /// and the previous content is kept as a version.
+    ///
+    /// Only the owner of an entry and admins may refresh it.
+    async fn refresh_entry(&self, id: &str) -> Result<RefreshResult>;
+
+    /// Lists the versions of an entry's content, oldest first and ending with the current content.
+    async fn versions(&self, id: &str) -> Result<Vec<EntryVersion>>;
+
+    /// Reports the embedding tokens consumed and the budgets that apply to the user.
+    async fn usage(&self) -> Result<UsageReport>;
+
+    /// Counts the entries the user may see by the last checked status of their original link,
+    /// and lists the entries with the given status, such as the originals that are gone.
+    async fn link_report(&self, status: OriginalStatus) -> Result<LinkReport>;
 }
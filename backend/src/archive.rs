@@ -0,0 +1,349 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tempdir::TempDir;
+use tracing::warn;
+
+use crate::{client, storage, LocalClient};
+
+/// The path of the manifest within an archive
+const MANIFEST: &str = "manifest.ndjson";
+/// The directory of the stored files within an archive, each named by its CID
+const BLOBS: &str = "blobs";
+
+/// One line of an archive's manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub id: String,
+    pub payload: Value,
+    /// The embedding vector, restoring an entry without one embeds its description again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+    /// The SHA-256 hashes of the files the entry references, by CID
+    #[serde(default)]
+    pub blobs: BTreeMap<String, String>,
+}
+
+/// An entry or file that couldn't be exported or restored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveFailure {
+    /// The entry ID or CID
+    pub id: String,
+    pub error: String,
+}
+
+/// The summary of an export or restore
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ArchiveReport {
+    pub entries: usize,
+    pub blobs: usize,
+    pub failed: Vec<ArchiveFailure>,
+}
+
+/// Writes every entry and the files they reference to a gzipped tarball.
+///
+/// Files that can't be fetched from storage are reported and left out of the archive.
+pub async fn export(
+    client: &LocalClient,
+    path: &Path,
+    with_vectors: bool,
+) -> Result<ArchiveReport> {
+    let staging = TempDir::new("socialmediaexport")?;
+    let entries = client
+        .vector
+        .all(with_vectors)
+        .await
+        .context("failed to list entries")?;
+
+    let file = File::create(path)
+        .with_context(|| format!("failed to create archive {}", path.display()))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let manifest_path = staging.path().join(MANIFEST);
+    let mut manifest = File::create(&manifest_path)?;
+
+    let mut report = ArchiveReport::default();
+    let mut hashes: HashMap<String, String> = HashMap::new();
+    for stored in entries {
+        let mut blobs = BTreeMap::new();
+        for cid in storage::referenced_cids(&stored.entry.payload) {
+            if let Some(hash) = hashes.get(&cid) {
+                blobs.insert(cid, hash.clone());
+                continue;
+            }
+
+            let blob = staging.path().join(&cid);
+            match fetch_blob(client, &cid, &blob).await {
+                Ok(hash) => {
+                    tar.append_path_with_name(&blob, format!("{BLOBS}/{cid}"))
+                        .context("failed to write archive")?;
+                    std::fs::remove_file(&blob)?;
+                    report.blobs += 1;
+                    hashes.insert(cid.clone(), hash.clone());
+                    blobs.insert(cid, hash);
+                }
+                Err(e) => {
+                    warn!("couldn't export {cid} of entry {}: {e:#}", stored.entry.id);
+                    report.failed.push(ArchiveFailure {
+                        id: cid,
+                        error: format!("{e:#}"),
+                    });
+                }
+            }
+        }
+
+        let line = ManifestEntry {
+            id: stored.entry.id,
+            payload: stored.entry.payload,
+            vector: stored.vector,
+            blobs,
+        };
+        writeln!(&mut manifest, "{}", serde_json::to_string(&line)?)?;
+        report.entries += 1;
+    }
+
+    manifest.flush()?;
+    tar.append_path_with_name(&manifest_path, MANIFEST)
+        .context("failed to write archive")?;
+    tar.into_inner()?
+        .finish()
+        .context("failed to write archive")?;
+    Ok(report)
+}
+
+/// Fetches a stored file to the given path, returns its SHA-256 hash.
+pub(crate) async fn fetch_blob(client: &LocalClient, cid: &str, path: &Path) -> Result<String> {
+    let mut file = File::create(path)?;
+    let mut hasher = Sha256::new();
+    let mut content = client.storage.cat(cid);
+    while let Some(chunk) = content.try_next().await? {
+        hasher.update(&chunk);
+        file.write_all(&chunk)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Restores the entries and files of an archive made by `export`, keeping entry IDs and replacing
+/// entries with the same ID.
+///
+/// Entries referencing a file whose hash doesn't match the manifest aren't restored. If storage
+/// assigns a file a different CID than the archive has, the restored payloads are updated to match.
+/// Sidecar bundles aren't archived, they're stored again from their files.
+pub async fn restore(client: &LocalClient, path: &Path) -> Result<ArchiveReport> {
+    let staging = TempDir::new("socialmediarestore")?;
+    let file =
+        File::open(path).with_context(|| format!("failed to open archive {}", path.display()))?;
+
+    // archives are gzipped, but plain tarballs are accepted too
+    let mut file = BufReader::new(file);
+    let archive: Box<dyn Read> = match file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        true => Box::new(GzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    tar::Archive::new(archive)
+        .unpack(staging.path())
+        .context("failed to unpack archive")?;
+
+    let manifest = File::open(staging.path().join(MANIFEST)).context("archive has no manifest")?;
+
+    let mut report = ArchiveReport::default();
+    // the CIDs of the restored files by their CID in the archive
+    let mut restored: HashMap<String, String> = HashMap::new();
+    for (i, line) in BufReader::new(manifest).lines().enumerate() {
+        let entry: ManifestEntry = serde_json::from_str(&line?)
+            .with_context(|| format!("invalid manifest line {}", i + 1))?;
+        let id = entry.id.clone();
+
+        match restore_entry(client, staging.path(), entry, &mut restored).await {
+            Ok(()) => report.entries += 1,
+            Err(e) => {
+                warn!("couldn't restore entry {id}: {e:#}");
+                report.failed.push(ArchiveFailure {
+                    id,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+
+    report.blobs = restored.len();
+    Ok(report)
+}
+
+async fn restore_entry(
+    client: &LocalClient,
+    staging: &Path,
+    entry: ManifestEntry,
+    restored: &mut HashMap<String, String>,
+) -> Result<()> {
+    let ManifestEntry {
+        id,
+        mut payload,
+        vector,
+        blobs,
+    } = entry;
+
+    for (cid, hash) in &blobs {
+        if restored.contains_key(cid) {
+            continue;
+        }
+
+        let blob = staging.join(BLOBS).join(cid);
+        let actual = hash_file(&blob).with_context(|| format!("archive is missing {cid}"))?;
+        if actual != *hash {
+            bail!("{cid} doesn't match its hash in the manifest");
+        }
+
+        let new_cid = client
+            .storage
+            .save_file(&blob)
+            .await
+            .with_context(|| format!("failed to store {cid}"))?;
+        if new_cid.0 != *cid {
+            warn!("{cid} was stored as {new_cid}, updating entries that reference it");
+        }
+        restored.insert(cid.clone(), new_cid.0);
+    }
+    // sidecar bundles aren't exported, so they're stored again from the restored sidecar files
+    if let Some(sidecars) = payload.get_mut("sidecars") {
+        restore_bundle(client, staging, &blobs, sidecars).await?;
+    }
+    if let Some(versions) = payload.get_mut("versions").and_then(Value::as_array_mut) {
+        for sidecars in versions.iter_mut().filter_map(|v| v.get_mut("sidecars")) {
+            restore_bundle(client, staging, &blobs, sidecars).await?;
+        }
+    }
+    replace_cids(&mut payload, restored);
+
+    let vector = match vector {
+        Some(vector) => vector,
+        None => {
+            let description = payload["description"].as_str().unwrap_or_default();
+            let text = payload["text"].as_str();
+            client
+                .embed(&client::embedding_input(description, text))
+                .await?
+        }
+    };
+    client.vector.upsert(&id, vector, payload).await
+}
+
+/// Stores the sidecar files of an entry as a bundle again and replaces the bundle's CID, which is
+/// the one of the exporting node otherwise.
+async fn restore_bundle(
+    client: &LocalClient,
+    staging: &Path,
+    blobs: &BTreeMap<String, String>,
+    sidecars: &mut Value,
+) -> Result<()> {
+    if sidecars["bundle"].is_null() {
+        return Ok(());
+    }
+    let dir = TempDir::new_in(staging, "sidecars")?;
+    stage_bundle(staging, blobs, sidecars, dir.path())?;
+    let (bundle, _) = client
+        .storage
+        .save_dir(dir.path())
+        .await
+        .context("failed to store sidecar files")?;
+    sidecars["bundle"] = json!(bundle.0);
+    Ok(())
+}
+
+/// Copies the sidecar files in the archive to `dir` under their names. Files the archive is missing,
+/// since they couldn't be exported, are left out of the bundle.
+fn stage_bundle(
+    staging: &Path,
+    blobs: &BTreeMap<String, String>,
+    sidecars: &Value,
+    dir: &Path,
+) -> Result<()> {
+    for file in sidecars["files"].as_array().into_iter().flatten() {
+        let name = file["name"].as_str().context("sidecar file has no name")?;
+        let cid = file["cid"].as_str().context("sidecar file has no CID")?;
+        ensure!(
+            Path::new(name).file_name() == Some(name.as_ref()),
+            "invalid sidecar file name {name}"
+        );
+        if !blobs.contains_key(cid) {
+            warn!("archive is missing sidecar file {name}, leaving it out of its bundle");
+            continue;
+        }
+        std::fs::copy(staging.join(BLOBS).join(cid), dir.join(name))
+            .with_context(|| format!("archive is missing {cid}"))?;
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Replaces every string in the payload that is a restored CID with its new CID.
+fn replace_cids(value: &mut Value, restored: &HashMap<String, String>) {
+    match value {
+        Value::String(s) => {
+            if let Some(new_cid) = restored.get(s.as_str()) {
+                *s = new_cid.clone();
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| replace_cids(v, restored)),
+        Value::Object(fields) => fields.values_mut().for_each(|v| replace_cids(v, restored)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_are_staged_from_archived_sidecars() {
+        let staging = TempDir::new("archivetest").unwrap();
+        std::fs::create_dir(staging.path().join(BLOBS)).unwrap();
+        std::fs::write(staging.path().join(BLOBS).join("a"), "subtitles").unwrap();
+        let blobs = BTreeMap::from([("a".to_string(), "hash".to_string())]);
+        let sidecars = json!({
+            "bundle": "old",
+            "files": [
+                { "name": "video.en.vtt", "cid": "a" },
+                // couldn't be exported
+                { "name": "video.info.json", "cid": "b" },
+            ],
+        });
+
+        let dir = TempDir::new_in(staging.path(), "sidecars").unwrap();
+        stage_bundle(staging.path(), &blobs, &sidecars, dir.path()).unwrap();
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|file| file.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["video.en.vtt"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("video.en.vtt")).unwrap(),
+            "subtitles"
+        );
+    }
+
+    #[test]
+    fn sidecar_names_stay_in_the_bundle() {
+        let staging = TempDir::new("archivetest").unwrap();
+        let blobs = BTreeMap::from([("a".to_string(), "hash".to_string())]);
+        let sidecars = json!({ "bundle": "old", "files": [{ "name": "../escape", "cid": "a" }] });
+        let dir = TempDir::new_in(staging.path(), "sidecars").unwrap();
+        assert!(stage_bundle(staging.path(), &blobs, &sidecars, dir.path()).is_err());
+    }
+}
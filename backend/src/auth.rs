@@ -0,0 +1,215 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{dev::ServiceRequest, error::InternalError, http::header, HttpResponse};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_value, json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{api::Visibility, config};
+
+/// The prefix of every API token secret, makes leaked tokens easy to recognize
+const SECRET_PREFIX: &str = "sma_";
+
+/// The cookie the web UI keeps the API token secret in after logging in
+pub const SESSION_COOKIE: &str = "sma_session";
+
+/// An API token accepted by the daemon. Only the SHA-256 hash of the secret is stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub admin: bool,
+    /// Unix timestamp in seconds of when the token was created
+    pub created_at: u64,
+    hash: String,
+}
+
+/// The caller a daemon request was authenticated as.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ApiUser {
+    /// The ID of the token used, `local` for callers that didn't need one
+    pub id: String,
+    pub name: String,
+    pub admin: bool,
+}
+
+impl ApiUser {
+    /// The user of a `LocalClient` that isn't acting for anyone else, or of a daemon without authentication.
+    pub fn local() -> Self {
+        Self {
+            id: "local".to_string(),
+            name: "local".to_string(),
+            admin: true,
+        }
+    }
+
+    /// Whether the user added the entry with the given payload.
+    pub fn owns(&self, payload: &Value) -> bool {
+        payload["added_by"].as_str() == Some(&self.id)
+    }
+
+    /// Whether the user may see the entry with the given payload.
+    /// Entries archived before visibility was recorded are visible to the team.
+    pub fn can_see(&self, payload: &Value) -> bool {
+        let visibility = match payload.get("visibility") {
+            Some(v) => from_value(v.clone()).unwrap_or(Visibility::Private),
+            None => Visibility::Team,
+        };
+        self.admin || self.owns(payload) || visibility != Visibility::Private
+    }
+}
+
+impl From<&ApiToken> for ApiUser {
+    fn from(token: &ApiToken) -> Self {
+        Self {
+            id: token.id.clone(),
+            name: token.name.clone(),
+            admin: token.admin,
+        }
+    }
+}
+
+/// The tokens file as last read, along with its modification time
+#[derive(Default)]
+struct CachedTokens {
+    modified: Option<SystemTime>,
+    tokens: Vec<ApiToken>,
+}
+
+/// The API tokens stored in a json file. The daemon rereads the file when it changes,
+/// so tokens managed from the CLI take effect without a restart.
+/// Clones are referenced counted.
+#[derive(Clone)]
+pub struct TokenStore {
+    path: PathBuf,
+    cache: Arc<Mutex<CachedTokens>>,
+}
+
+impl TokenStore {
+    /// Uses the file at `TOKENS_FILE`, or `tokens.json` in the working directory.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            path: config::var_or("TOKENS_FILE", PathBuf::from("tokens.json"))?,
+            cache: Default::default(),
+        })
+    }
+
+    /// Lists the stored tokens.
+    pub fn list(&self) -> Result<Vec<ApiToken>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let file = std::fs::read(&self.path)
+            .with_context(|| format!("failed to read tokens file {}", self.path.display()))?;
+        serde_json::from_slice(&file).context("failed to parse tokens file")
+    }
+
+    fn save(&self, tokens: &[ApiToken]) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(tokens)?)
+            .with_context(|| format!("failed to write tokens file {}", self.path.display()))
+    }
+
+    /// Creates a new token, returns it along with its secret. The secret can't be recovered later.
+    pub fn create(&self, name: &str, admin: bool) -> Result<(ApiToken, String)> {
+        let mut tokens = self.list()?;
+        if tokens.iter().any(|t| t.name == name) {
+            bail!("a token named {name} already exists");
+        }
+
+        let secret = format!(
+            "{SECRET_PREFIX}{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let token = ApiToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            admin,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            hash: hash_secret(&secret),
+        };
+
+        tokens.push(token.clone());
+        self.save(&tokens)?;
+        Ok((token, secret))
+    }
+
+    /// Revokes the token with the given ID or name, returns the revoked token.
+    pub fn revoke(&self, id_or_name: &str) -> Result<ApiToken> {
+        let mut tokens = self.list()?;
+        let Some(index) = tokens
+            .iter()
+            .position(|t| t.id == id_or_name || t.name == id_or_name)
+        else {
+            bail!("no token with ID or name {id_or_name}");
+        };
+
+        let token = tokens.remove(index);
+        self.save(&tokens)?;
+        Ok(token)
+    }
+
+    /// Finds the user a secret belongs to, rereading the tokens file if it changed.
+    pub fn authenticate(&self, secret: &str) -> Result<Option<ApiUser>> {
+        let mut cache = self.cache.lock().unwrap();
+
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified != cache.modified {
+            *cache = CachedTokens {
+                modified,
+                tokens: self.list()?,
+            };
+        }
+
+        let hash = hash_secret(secret);
+        Ok(cache
+            .tokens
+            .iter()
+            .find(|t| t.hash == hash)
+            .map(ApiUser::from))
+    }
+
+    /// Authenticates a daemon request by its `Authorization: Bearer` header,
+    /// or else by the web UI's session cookie.
+    pub fn authenticate_request(&self, req: &ServiceRequest) -> Result<Option<ApiUser>> {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let Some(secret) =
+            bearer.or_else(|| req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()))
+        else {
+            return Ok(None);
+        };
+
+        self.authenticate(secret.trim())
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// The error returned to requests without a valid API token
+pub fn unauthorized() -> actix_web::Error {
+    InternalError::from_response(
+        "missing or invalid API token",
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json(json!({"error": "missing or invalid API token"})),
+    )
+    .into()
+}
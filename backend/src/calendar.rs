@@ -0,0 +1,115 @@
+/// The UTC date of a unix timestamp in seconds, formatted as `YYYY-MM-DD`
+pub fn utc_date(timestamp: u64) -> String {
+    let days = timestamp / 86400;
+
+    // converts days since the unix epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The unix timestamp in seconds of a UTC date and time, `None` if it's invalid or before 1970
+pub fn utc_timestamp(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Option<u64> {
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // the inverse of `utc_date`
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u64::try_from(
+        days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second),
+    )
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_utc_dates() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(86399), "1970-01-01");
+        assert_eq!(utc_date(86400), "1970-01-02");
+        // 2023-11-20T12:00:00Z
+        assert_eq!(utc_date(1700481600), "2023-11-20");
+    }
+
+    #[test]
+    fn formats_utc_dates_across_month_and_year_ends() {
+        // 2023-01-31T23:59:59Z and the second after
+        assert_eq!(utc_date(1675209599), "2023-01-31");
+        assert_eq!(utc_date(1675209600), "2023-02-01");
+        // 2023-12-31T23:59:59Z and the second after
+        assert_eq!(utc_date(1704067199), "2023-12-31");
+        assert_eq!(utc_date(1704067200), "2024-01-01");
+    }
+
+    #[test]
+    fn formats_utc_dates_in_leap_years() {
+        // 2024-02-28, 2024-02-29 and 2024-03-01
+        assert_eq!(utc_date(1709078400), "2024-02-28");
+        assert_eq!(utc_date(1709164800), "2024-02-29");
+        assert_eq!(utc_date(1709251200), "2024-03-01");
+        // 2023 isn't a leap year, 2000 is although it's divisible by 100, 2100 isn't
+        assert_eq!(utc_date(1677628800), "2023-03-01");
+        assert_eq!(utc_date(951782400), "2000-02-29");
+        assert_eq!(utc_date(4107542400), "2100-03-01");
+    }
+
+    #[test]
+    fn converts_dates_to_timestamps() {
+        assert_eq!(utc_timestamp(1970, 1, 1, 0, 0, 0), Some(0));
+        assert_eq!(utc_timestamp(2024, 2, 29, 0, 0, 0), Some(1709164800));
+        assert_eq!(utc_timestamp(2023, 12, 31, 23, 59, 59), Some(1704067199));
+        assert_eq!(utc_timestamp(1969, 12, 31, 23, 59, 59), None);
+        assert_eq!(utc_timestamp(2023, 13, 1, 0, 0, 0), None);
+        assert_eq!(utc_timestamp(2023, 1, 0, 0, 0, 0), None);
+        assert_eq!(utc_timestamp(2023, 1, 1, 24, 0, 0), None);
+    }
+
+    #[test]
+    fn timestamps_round_trip_through_dates() {
+        for days in (0..200_000).step_by(97) {
+            let date = utc_date(days * 86400);
+            let [year, month, day]: [&str; 3] =
+                date.split('-').collect::<Vec<_>>().try_into().unwrap();
+            let timestamp = utc_timestamp(
+                year.parse().unwrap(),
+                month.parse().unwrap(),
+                day.parse().unwrap(),
+                0,
+                0,
+                0,
+            );
+            assert_eq!(timestamp, Some(days * 86400), "{date}");
+        }
+    }
+}
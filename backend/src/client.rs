@@ -1,18 +1,29 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{
     api::*,
-    daemon::{self, AddLink, Task},
-    download::DownloadClient,
-    embeddings::EmbeddingClient,
-    storage::StorageClient,
-    vector::VectorDbClient,
+    auth::ApiUser,
+    daemon::{self, Task, TaskEvent},
+    download::{DownloadClient, Sidecar},
+    embeddings::{self, EmbeddingClient},
+    health::{LinkChecker, LinkReport, OriginalStatus},
+    network::Network,
+    preview::PreviewClient,
+    quota::{UsageReport, UsageTracker},
+    storage::{self, StorageClient},
+    vector::{self, VectorDbClient},
 };
 use anyhow::*;
 use async_trait::async_trait;
+use reqwest::{Method, RequestBuilder, StatusCode};
 use serde::de::DeserializeOwned;
-use serde_json::{from_value, json};
+use serde_json::{from_value, json, to_value, Value};
 use tempdir::TempDir;
+use tracing::warn;
 
 /// A top-level client that encapsulates all required components and provides the logical operations.
 /// Clones are referenced counted.
@@ -23,14 +34,25 @@ pub struct LocalClient {
     pub vector: VectorDbClient,
     pub storage: StorageClient,
     pub download: DownloadClient,
+    pub previews: PreviewClient,
+    pub links: LinkChecker,
+    pub usage: UsageTracker,
+    /// The user the client acts on behalf of
+    pub user: ApiUser,
 }
 
 impl LocalClient {
     pub async fn new() -> Result<Self> {
-        let embeddings = EmbeddingClient::new().context("failed to create embeddings client")?;
+        let network = Network::from_env().context("failed to read network configuration")?;
+        let embeddings =
+            EmbeddingClient::new(&network).context("failed to create embeddings client")?;
         let mut vector = VectorDbClient::new().context("failed to create vectordb client")?;
-        let download = DownloadClient::new().context("failed to create download client")?;
+        let download = DownloadClient::new(&network).context("failed to create download client")?;
+        let previews = PreviewClient::new().context("failed to create preview client")?;
+        let links =
+            LinkChecker::new(&download, &network).context("failed to create link checker")?;
         let mut storage = StorageClient::new().context("failed to create storage client")?;
+        let usage = UsageTracker::from_env().context("failed to create usage tracker")?;
 
         vector
             .init()
@@ -46,36 +68,190 @@ impl LocalClient {
             vector,
             storage,
             download,
+            previews,
+            links,
+            usage,
+            user: ApiUser::local(),
         })
     }
 
-    /// Runs the client as a daemon serving over REST
-    pub async fn daemonize(self) -> Result<()> {
-        Ok(daemon::run(self).await?)
+    /// A client that acts on behalf of the given user, sharing all components with this client.
+    pub fn as_user(&self, user: ApiUser) -> Self {
+        Self {
+            user,
+            ..self.clone()
+        }
     }
-}
 
-#[async_trait(?Send)]
-impl ClientApi for LocalClient {
-    async fn add_link(&self, link: &str, description: &str) -> Result<Entry> {
-        let temp = TempDir::new("socialmediadownload")?;
+    /// Generates embeddings for the text, charging the consumed tokens to the client's user.
+    /// Fails if the embedding isn't cached and the user's daily budget is exhausted.
+    pub(crate) async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let reservation = match self.embeddings.is_cached(text) {
+            true => None,
+            false => Some(
+                self.usage
+                    .check_budget(&self.user.id, embeddings::estimate_tokens(text) as u64)?,
+            ),
+        };
+        let embedding = self.embeddings.generate(text).await?;
+        self.usage
+            .record(&self.user.id, embedding.tokens, reservation)?;
+        Ok(embedding.vector)
+    }
 
-        // download the requested link
-        let outfile = self
-            .download
-            .download(link, temp.path())
+    /// Stores a downloaded file with its generated thumbnail and preview, and the file it was converted
+    /// from if that is kept, returns the media item for the payload.
+    async fn store_media(
+        &self,
+        link: &str,
+        file: &Path,
+        original: Option<&PathBuf>,
+    ) -> Result<Value> {
+        let (content_type, _) = storage::sniff_file(file)?;
+        let previews = self
+            .previews
+            .generate(file, content_type)
             .await
-            .context("failed to download link")?;
+            .unwrap_or_else(|e| {
+                warn!("couldn't generate previews of {link}: {e:#}");
+                Default::default()
+            });
 
         let cid = self
             .storage
-            .save_file(outfile)
+            .save_file(file)
             .await
             .context("failed to store downloaded file")?;
+        Ok(json!({
+            "cid": cid.0,
+            "content_type": content_type,
+            "thumbnail": self.store_preview(previews.thumbnail, "image/jpeg").await?,
+            "preview": self.store_preview(previews.preview, "video/mp4").await?,
+            "original": match original {
+                Some(original) => {
+                    let (content_type, _) = storage::sniff_file(original)?;
+                    self.store_preview(Some(original.clone()), content_type).await?
+                }
+                None => Value::Null,
+            },
+        }))
+    }
+
+    /// Stores the sidecar files as a bundle, returns the bundle's directory CID and its files for the payload.
+    /// Each file is listed with its own CID, so they can be served and exported on their own.
+    async fn store_sidecars(&self, dir: &Path) -> Result<Value> {
+        let (bundle, files) = self
+            .storage
+            .save_dir(dir)
+            .await
+            .context("failed to store sidecar files")?;
+        let files: Vec<Value> = files
+            .into_iter()
+            .map(|(name, cid)| {
+                let (kind, content_type) = Sidecar::of_file(&name);
+                json!({
+                    "name": name,
+                    "kind": kind,
+                    "cid": cid.0,
+                    "content_type": content_type,
+                })
+            })
+            .collect();
+        Ok(json!({ "bundle": bundle.0, "files": files }))
+    }
+
+    /// Stores a generated or original file, returns its CID and content type for the payload.
+    async fn store_preview(&self, file: Option<PathBuf>, content_type: &str) -> Result<Value> {
+        let Some(file) = file else {
+            return Ok(Value::Null);
+        };
+        let cid = self
+            .storage
+            .save_file(file)
+            .await
+            .context("failed to store preview file")?;
+        Ok(json!({ "cid": cid.0, "content_type": content_type }))
+    }
+
+    /// Downloads the link and stores its files, returns the payload fields describing its content
+    /// (see `CONTENT_FIELDS`). The main media's fields are those of the first media item.
+    ///
+    /// Links are downloaded by the downloader they're routed to, falling back to another, unless
+    /// a downloader is given.
+    async fn archive(&self, link: &str, downloader: Option<&str>) -> Result<Value> {
+        let temp = TempDir::new("socialmediadownload")?;
+
+        daemon::report_progress("downloading").await;
+        let download = match downloader {
+            Some(name) => self.download.download_with(link, temp.path(), name).await,
+            None => self.download.download(link, temp.path()).await,
+        }
+        .context("failed to download link")?;
+
+        daemon::report_progress("storing").await;
+        let mut media = Vec::with_capacity(download.media.len());
+        for outfile in &download.media {
+            let original = download.originals.get(outfile);
+            media.push(self.store_media(link, outfile, original).await?);
+        }
+        // the first item is the entry's main media
+        let main = media
+            .first()
+            .cloned()
+            .context("downloader returned no media")?;
+        let sidecars = match &download.sidecars {
+            Some(dir) => self.store_sidecars(dir).await?,
+            None => Value::Null,
+        };
+
+        Ok(json!({
+            "cid": main["cid"],
+            "content_type": main["content_type"],
+            "thumbnail": main["thumbnail"],
+            "preview": main["preview"],
+            "media": media,
+            "sidecars": sidecars,
+            "text": download.text,
+            "downloader": download.downloader,
+        }))
+    }
+
+    /// Runs the client as a daemon serving over REST
+    pub async fn daemonize(self) -> Result<()> {
+        Ok(daemon::run(self).await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl ClientApi for LocalClient {
+    async fn add_link(&self, input: &AddLink) -> Result<Entry> {
+        let AddLink {
+            link,
+            description,
+            visibility,
+            tags,
+            collection,
+            posted_at,
+        } = input;
+        // download the requested link and store its files
+        let content = self.archive(link, None).await?;
 
         // generate embeddings and store in vector db
-        let embeddings = self.embeddings.generate(description.trim()).await?;
-        let payload = json!({"description": description, "original_link": link, "cid": cid.0});
+        daemon::report_progress("embedding").await;
+        let embeddings = self
+            .embed(&embedding_input(description, content["text"].as_str()))
+            .await?;
+        let mut payload = json!({
+            "description": description,
+            "original_link": link,
+            "added_by": self.user.id,
+            "visibility": visibility,
+            "tags": tags,
+            "collection": collection,
+            "posted_at": posted_at,
+            "added_at": daemon::unix_time(),
+        });
+        merge(&mut payload, content);
         let id = self
             .vector
             .insert_vector(embeddings, payload.clone())
@@ -84,79 +260,471 @@ impl ClientApi for LocalClient {
     }
 
     async fn search(&self, description: &str) -> Result<SearchResult> {
+        daemon::report_progress("embedding").await;
         let embedding = self
-            .embeddings
-            .generate(description.trim())
+            .embed(description.trim())
             .await
             .context("failed to generate embedding for description")?;
+        daemon::report_progress("searching").await;
         self.vector
-            .search(embedding)
+            .search(embedding, vector::visible_to(&self.user))
             .await
             .context("failed to search vector db")
     }
+
+    async fn get_entry(&self, id: &str) -> Result<Entry> {
+        match self.vector.get(id).await? {
+            Some(entry) if self.user.can_see(&entry.payload) => Ok(entry),
+            _ => bail!("no entry with ID {id}"),
+        }
+    }
+
+    async fn update_entry(&self, id: &str, update: &EntryUpdate) -> Result<Entry> {
+        let entry = self.get_entry(id).await?;
+        ensure!(
+            self.user.admin || self.user.owns(&entry.payload),
+            "only the owner of an entry or an admin may change it"
+        );
+        ensure!(
+            self.user.admin || update.added_by.is_none(),
+            "only admins may reassign the owner of an entry"
+        );
+
+        let fields = to_value(update)?;
+        match &update.description {
+            Some(description) => {
+                // a new description needs a new vector, which replaces the whole entry
+                let text = entry.payload["text"].as_str();
+                let vector = self.embed(&embedding_input(description, text)).await?;
+                let mut payload = entry.payload;
+                merge(&mut payload, fields);
+                self.vector.upsert(id, vector, payload).await
+            }
+            None => self.vector.set_payload(id, fields).await,
+        }
+        .context("failed to update entry")?;
+        self.vector
+            .get(id)
+            .await?
+            .context("entry was removed while updating it")
+    }
+
+    async fn refresh_entry(&self, id: &str) -> Result<RefreshResult> {
+        let entry = self.get_entry(id).await?;
+        ensure!(
+            self.user.admin || self.user.owns(&entry.payload),
+            "only the owner of an entry or an admin may refresh it"
+        );
+        let link = entry.payload["original_link"]
+            .as_str()
+            .context("entry has no original link")?;
+
+        // a real check rather than the download, since sites answer links to deleted posts with pages of their own
+        daemon::report_progress("checking").await;
+        let status = self.links.check(link).await;
+        let now = daemon::unix_time();
+        let mut fields = json!({ "original_status": status, "last_checked": now });
+        self.vector.set_payload(id, fields.clone()).await?;
+        ensure!(
+            status != OriginalStatus::Gone,
+            "the original is gone, the archived content was kept"
+        );
+
+        // the downloader that archived the entry, without a fallback that would archive an error page instead
+        let downloader = match entry.payload["downloader"].as_str() {
+            Some(downloader) => downloader.to_string(),
+            // entries archived before downloaders were recorded
+            None if entry.payload["text"].is_string() => "snapshot".to_string(),
+            None => self.download.route(link).0.name().to_string(),
+        };
+        let content = self.archive(link, Some(&downloader)).await?;
+        let changed = content_changed(&entry.payload, &content)?;
+        if !changed {
+            // files stored again, like sidecars with new download times, stay pinned until `backend fsck --gc`
+            fields["downloader"] = content["downloader"].clone();
+            self.vector.set_payload(id, fields).await?;
+        } else {
+            daemon::report_progress("storing version").await;
+            let mut version = json!({
+                "archived_at": entry.payload["refreshed_at"]
+                    .as_u64()
+                    .or(entry.payload["added_at"].as_u64()),
+            });
+            for field in CONTENT_FIELDS {
+                version[field] = entry.payload[field].clone();
+            }
+            let mut versions = entry.payload["versions"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            versions.push(version);
+
+            let text_changed = entry.payload["text"] != content["text"];
+            merge(&mut fields, content);
+            merge(
+                &mut fields,
+                json!({ "versions": versions, "refreshed_at": now }),
+            );
+            match text_changed {
+                true => {
+                    // the text is embedded along with the description, so the entry needs a new vector
+                    daemon::report_progress("embedding").await;
+                    let description = entry.payload["description"].as_str().unwrap_or_default();
+                    let vector = self
+                        .embed(&embedding_input(description, fields["text"].as_str()))
+                        .await?;
+                    let mut payload = entry.payload;
+                    merge(&mut payload, fields);
+                    self.vector.upsert(id, vector, payload).await?;
+                }
+                false => self.vector.set_payload(id, fields).await?,
+            }
+        }
+
+        let entry = self
+            .vector
+            .get(id)
+            .await?
+            .context("entry was removed while refreshing it")?;
+        Ok(RefreshResult { changed, entry })
+    }
+
+    async fn versions(&self, id: &str) -> Result<Vec<EntryVersion>> {
+        let entry = self.get_entry(id).await?;
+        Ok(EntryVersion::of_payload(&entry.payload))
+    }
+
+    async fn usage(&self) -> Result<UsageReport> {
+        Ok(UsageReport {
+            embedding_cache: self.embeddings.cache_stats(),
+            ..self.usage.report(&self.user.id, self.user.admin)
+        })
+    }
+
+    async fn link_report(&self, status: OriginalStatus) -> Result<LinkReport> {
+        let mut report = LinkReport::default();
+        for stored in self.vector.all(false).await? {
+            let entry = stored.entry;
+            if !self.user.can_see(&entry.payload) {
+                continue;
+            }
+            let Some(checked) =
+                from_value::<OriginalStatus>(entry.payload["original_status"].clone()).ok()
+            else {
+                report.unchecked += 1;
+                continue;
+            };
+            match checked {
+                OriginalStatus::Available => report.available += 1,
+                OriginalStatus::Gone => report.gone += 1,
+                OriginalStatus::Error => report.error += 1,
+            }
+            if checked == status {
+                report.entries.push(entry);
+            }
+        }
+        report
+            .entries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.payload["last_checked"].as_u64()));
+        Ok(report)
+    }
+}
+
+/// Whether refreshed content differs from an entry's current content. Snapshots are compared by their text
+/// only, since pages differ in tokens, ads and timestamps on every fetch, and other content by its media CIDs
+/// and text. Fails if the content is of another kind, like a page where there was a video, which is more
+/// likely an error page of the site than the post.
+fn content_changed(current: &Value, refreshed: &Value) -> Result<bool> {
+    let kind = |content: &Value| {
+        content["content_type"]
+            .as_str()
+            .and_then(|content_type| content_type.split('/').next())
+            .map(str::to_string)
+    };
+    let (current_kind, refreshed_kind) = (kind(current), kind(refreshed));
+    ensure!(
+        current_kind.is_none() || current_kind == refreshed_kind,
+        "the link now has {} content rather than {}, the archived content was kept",
+        refreshed_kind.as_deref().unwrap_or("unknown"),
+        current_kind.as_deref().unwrap_or("unknown"),
+    );
+
+    if refreshed["downloader"] == "snapshot" {
+        return Ok(current["text"] != refreshed["text"]);
+    }
+    // CIDs are content hashes, so the media changed if any of their CIDs did
+    let media_cids = |content: &Value| -> Vec<Value> {
+        match content["media"].as_array() {
+            Some(items) => items.iter().map(|item| item["cid"].clone()).collect(),
+            // entries added before media lists only have the main media
+            None => vec![content["cid"].clone()],
+        }
+    };
+    Ok(media_cids(current) != media_cids(refreshed) || current["text"] != refreshed["text"])
+}
+
+/// Sets the fields of `fields` in the payload, replacing fields that are already set.
+fn merge(payload: &mut Value, fields: Value) {
+    if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+        payload.extend(fields);
+    }
+}
+
+/// The most characters of a page's extracted text that are embedded along with the description
+const MAX_EMBEDDED_TEXT_CHARS: usize = 8000;
+
+/// The text an entry is embedded by: its description, followed by the start of the text
+/// extracted from its page if it was snapshotted.
+pub(crate) fn embedding_input(description: &str, text: Option<&str>) -> String {
+    let description = description.trim();
+    match text.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(text) => {
+            let text: String = text.chars().take(MAX_EMBEDDED_TEXT_CHARS).collect();
+            format!("{description}\n\n{text}").trim().to_string()
+        }
+        None => description.to_string(),
+    }
 }
 
+/// How long `RemoteClient` asks the daemon to hold a request open before following the task instead
+const SYNC_WAIT: &str = "30s";
+
 /// Similiar to a LocalClient but for daemons that are remote.
 /// Clones are referenced counted.
 #[derive(Clone)]
 pub struct RemoteClient {
     web_client: reqwest::Client,
     url: Arc<str>,
+    token: Option<Arc<str>>,
 }
 
 impl RemoteClient {
+    /// Authenticates with the token in the `API_TOKEN` env variable, if set.
     pub fn new(url: &str) -> Self {
         Self {
             web_client: reqwest::Client::new(),
             url: Arc::from(url),
+            token: std::env::var("API_TOKEN").ok().map(Arc::from),
+        }
+    }
+
+    /// Builds an authenticated request to the given API endpoint path.
+    fn request(&self, method: Method, endpoint: &str) -> RequestBuilder {
+        let request = self
+            .web_client
+            .request(method, format!("{}{endpoint}", self.url));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
         }
     }
 
+    /// Deserializes the result of a response from an endpoint that creates a task. If the daemon didn't
+    /// finish the task within the requested wait, waits for it to complete.
+    async fn task_response<T: DeserializeOwned>(&self, resp: reqwest::Response) -> Result<T> {
+        let resp = resp
+            .error_for_status()
+            .context("API endpoint returned an error")?;
+
+        let task = resp
+            .headers()
+            .get("location")
+            .unwrap()
+            .to_str()?
+            .to_string();
+
+        if resp.status() == StatusCode::OK {
+            if let Some(result) = task_result(resp.json().await?)? {
+                return Ok(result);
+            }
+        }
+
+        self.wait_for_task(&task).await
+    }
+
     /// Waits for the given task to complete and deserializes the result
+    ///
+    /// Follows the task's event stream, reconnecting if the stream ends before the task finishes.
     async fn wait_for_task<T: DeserializeOwned>(&self, task: &str) -> Result<T> {
+        let endpoint = format!("{task}/events");
         loop {
-            let resp = self
-                .web_client
-                .get(format!("{}{task}", self.url))
+            let mut resp = self
+                .request(Method::GET, &endpoint)
                 .send()
                 .await
-                .with_context(|| format!("failed to use API endpoint {task}"))?;
-            let task: Task = resp.json().await?;
-            match task {
-                Task::Cancelled => bail!("task was cancelled"),
-                Task::InProgress { .. } => tokio::time::sleep(Duration::from_secs(1)).await,
-                Task::Completed { data } => return Ok(from_value(data)?),
+                .with_context(|| format!("failed to use API endpoint {endpoint}"))?
+                .error_for_status()
+                .with_context(|| format!("API endpoint {endpoint} returned an error"))?;
+
+            let mut buffer = Vec::new();
+            while let Some(chunk) = resp.chunk().await? {
+                buffer.extend_from_slice(&chunk);
+
+                // server-sent events are separated by a blank line
+                while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                    let message: Vec<u8> = buffer.drain(..end + 2).collect();
+                    let message = std::str::from_utf8(&message)?;
+                    for data in message.lines().filter_map(|l| l.strip_prefix("data:")) {
+                        let event: TaskEvent = serde_json::from_str(data.trim())?;
+                        if let Some(result) = task_result(event.task)? {
+                            return Ok(result);
+                        }
+                    }
+                }
             }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
         }
     }
 }
 
+/// Deserializes the result of a finished task, `None` if it's still in progress.
+fn task_result<T: DeserializeOwned>(task: Task) -> Result<Option<T>> {
+    match task {
+        Task::Cancelled => bail!("task was cancelled"),
+        Task::InProgress { .. } => Ok(None),
+        Task::Completed { data } => match data.get("error").and_then(|e| e.as_str()) {
+            Some(error) => bail!("daemon task failed: {error}"),
+            None => Ok(Some(from_value(data)?)),
+        },
+    }
+}
+
+/// Deserializes the response of an endpoint that answers reads directly, or the error it returned.
+async fn read_response<T: DeserializeOwned>(resp: reqwest::Response) -> Result<T> {
+    if resp.status().is_success() {
+        return Ok(resp.json().await?);
+    }
+    let status = resp.status();
+    let body: Value = resp.json().await.unwrap_or_default();
+    match body["error"].as_str() {
+        Some(error) => bail!("API endpoint returned {status}: {error}"),
+        None => bail!("API endpoint returned {status}"),
+    }
+}
+
 #[async_trait(?Send)]
 impl ClientApi for RemoteClient {
-    async fn add_link(&self, link: &str, description: &str) -> Result<Entry> {
+    async fn add_link(&self, input: &AddLink) -> Result<Entry> {
         let resp = self
-            .web_client
-            .post(format!("{}/api/v0/add", self.url))
-            .json(&AddLink {
-                link: link.to_string(),
-                description: description.to_string(),
-            })
+            .request(Method::POST, &format!("/api/v0/add?wait={SYNC_WAIT}"))
+            .json(input)
             .send()
             .await
             .context("failed to use API endpoint /add")?;
-        let task = resp.headers().get("location").unwrap().to_str()?;
-        self.wait_for_task(task).await
+        self.task_response(resp).await
     }
 
     async fn search(&self, description: &str) -> Result<SearchResult> {
         let resp = self
-            .web_client
-            .post(format!("{}/api/v0/search", self.url))
+            .request(Method::POST, &format!("/api/v0/search?wait={SYNC_WAIT}"))
             .body(description.to_string())
             .send()
             .await
             .context("failed to use API endpoint /search")?;
-        let task = resp.headers().get("location").unwrap().to_str()?;
-        self.wait_for_task(task).await
+        self.task_response(resp).await
+    }
+
+    async fn get_entry(&self, id: &str) -> Result<Entry> {
+        let resp = self
+            .request(Method::GET, &format!("/api/v0/entries/{id}"))
+            .send()
+            .await
+            .context("failed to use API endpoint /entries")?;
+        read_response(resp).await
+    }
+
+    async fn update_entry(&self, id: &str, update: &EntryUpdate) -> Result<Entry> {
+        let resp = self
+            .request(
+                Method::PATCH,
+                &format!("/api/v0/entries/{id}?wait={SYNC_WAIT}"),
+            )
+            .json(update)
+            .send()
+            .await
+            .context("failed to use API endpoint /entries")?;
+        self.task_response(resp).await
+    }
+
+    async fn usage(&self) -> Result<UsageReport> {
+        let resp = self
+            .request(Method::GET, &format!("/api/v0/usage?wait={SYNC_WAIT}"))
+            .send()
+            .await
+            .context("failed to use API endpoint /usage")?;
+        self.task_response(resp).await
+    }
+
+    async fn refresh_entry(&self, id: &str) -> Result<RefreshResult> {
+        let resp = self
+            .request(
+                Method::POST,
+                &format!("/api/v0/entries/{id}/refresh?wait={SYNC_WAIT}"),
+            )
+            .send()
+            .await
+            .context("failed to use API endpoint /entries/refresh")?;
+        self.task_response(resp).await
+    }
+
+    async fn versions(&self, id: &str) -> Result<Vec<EntryVersion>> {
+        let resp = self
+            .request(Method::GET, &format!("/api/v0/entries/{id}/versions"))
+            .send()
+            .await
+            .context("failed to use API endpoint /entries/versions")?;
+        read_response(resp).await
+    }
+
+    async fn link_report(&self, status: OriginalStatus) -> Result<LinkReport> {
+        let resp = self
+            .request(
+                Method::GET,
+                &format!("/api/v0/originals?status={status}&wait={SYNC_WAIT}"),
+            )
+            .send()
+            .await
+            .context("failed to use API endpoint /originals")?;
+        self.task_response(resp).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::content_changed;
+    use serde_json::json;
+
+    #[test]
+    fn snapshots_are_compared_by_text() {
+        let current = json!({ "content_type": "text/html", "cid": "a", "text": "post", "downloader": "snapshot" });
+        let refetched = json!({ "content_type": "text/html", "cid": "b", "text": "post", "downloader": "snapshot" });
+        assert!(!content_changed(&current, &refetched).unwrap());
+        let edited = json!({ "content_type": "text/html", "cid": "c", "text": "edited post", "downloader": "snapshot" });
+        assert!(content_changed(&current, &edited).unwrap());
+    }
+
+    #[test]
+    fn media_is_compared_by_cids() {
+        let current =
+            json!({ "content_type": "video/mp4", "media": [{ "cid": "a" }, { "cid": "b" }] });
+        let same = json!({ "content_type": "video/mp4", "media": [{ "cid": "a" }, { "cid": "b" }], "downloader": "yt-dlp" });
+        assert!(!content_changed(&current, &same).unwrap());
+        let fewer = json!({ "content_type": "video/mp4", "media": [{ "cid": "a" }], "downloader": "yt-dlp" });
+        assert!(content_changed(&current, &fewer).unwrap());
+        // entries added before media lists
+        let old = json!({ "content_type": "video/mp4", "cid": "a" });
+        let single = json!({ "content_type": "video/mp4", "media": [{ "cid": "a" }], "downloader": "yt-dlp" });
+        assert!(!content_changed(&old, &single).unwrap());
+    }
+
+    #[test]
+    fn content_of_another_kind_is_kept() {
+        let current = json!({ "content_type": "video/mp4", "media": [{ "cid": "a" }] });
+        let error_page = json!({ "content_type": "text/html", "media": [{ "cid": "b" }], "downloader": "gallery-dl" });
+        let error = content_changed(&current, &error_page).unwrap_err();
+        assert!(error.to_string().contains("text content rather than video"));
+        let other_format = json!({ "content_type": "video/webm", "media": [{ "cid": "b" }], "downloader": "yt-dlp" });
+        assert!(content_changed(&current, &other_format).unwrap());
     }
 }
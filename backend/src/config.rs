@@ -0,0 +1,53 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use anyhow::{Context, Result};
+
+/// Reads and parses an optional env variable, `None` if it isn't set.
+pub fn var_opt<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+            .with_context(|| format!("{name} env variable is invalid")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads and parses an optional env variable, falling back to `default` if it isn't set.
+pub fn var_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    Ok(var_opt(name)?.unwrap_or(default))
+}
+
+/// A configured secret, like a password, that is kept out of logs and debug output
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// The secret itself, only to be handed to what needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
@@ -1,29 +1,131 @@
 use std::{
     collections::HashMap,
+    convert::Infallible,
     io::Write,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
     },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::LocalClient;
-use actix_web::{web, *};
+use crate::{
+    api::{ClientApi, EntryVersion},
+    auth::{self, ApiUser, TokenStore},
+    config,
+    health::{self, LinkMonitor},
+    quota::{self, RateLimiter},
+    storage, ui, LocalClient,
+};
+use actix_web::{dev::Service, web, *};
 use anyhow::Result;
-use futures::{future::abortable, stream::AbortHandle, Future, FutureExt};
+use futures::{
+    future::{self, abortable, ready, Either},
+    stream::{self, AbortHandle},
+    Future, FutureExt, Stream, StreamExt, TryStreamExt,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, to_value, Value};
-use tokio::sync::Mutex;
-use tracing::error;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Mutex,
+};
+use tracing::{error, info, warn};
 
 /// Global incrementing counter for task IDs
 pub static TASK_ID: AtomicU32 = AtomicU32::new(0);
 
+/// How many task events are buffered for slow event stream subscribers
+const EVENT_CAPACITY: usize = 1024;
+
+/// How often an idle event stream sends a comment to keep the connection open
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// The longest a request may wait for its task to finish before falling back to 202 Accepted
+const MAX_WAIT: Duration = Duration::from_secs(5 * 60);
+
+tokio::task_local! {
+    /// The daemon and task ID of the task that is running the current future
+    static CURRENT_TASK: (Daemon, u32);
+}
+
 /// The global data used in the daemon. Clones are referenced counted.
 #[derive(Clone)]
 pub struct Daemon {
     client: LocalClient,
-    tasks: Arc<Mutex<HashMap<u32, Task>>>,
+    tasks: Arc<Mutex<HashMap<u32, TaskInfo>>>,
+    events: broadcast::Sender<TaskEvent>,
+}
+
+/// A task along with the bookkeeping used to list and expire it
+#[derive(Serialize, Clone, Debug, Deserialize)]
+pub struct TaskInfo {
+    pub id: u32,
+    /// The endpoint that created the task, e.g. `search` or `add`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The ID of the user that created the task
+    pub owner: String,
+    /// Unix timestamp in seconds of when the task was created
+    pub created_at: u64,
+    /// Unix timestamp in seconds of the task's last state change
+    pub updated_at: u64,
+    pub task: Task,
+}
+
+/// How long finished tasks are kept around before the sweeper evicts them
+#[derive(Debug, Clone)]
+pub struct TaskRetention {
+    /// Finished tasks are evicted once they haven't changed for this long
+    pub ttl: Duration,
+    /// The most finished tasks kept, the oldest are evicted first
+    pub max_finished: usize,
+    /// How often the sweeper runs
+    pub sweep_interval: Duration,
+}
+
+impl TaskRetention {
+    /// Reads `TASK_TTL_SECS`, `TASK_RETENTION_MAX` and `TASK_SWEEP_INTERVAL_SECS`.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            ttl: Duration::from_secs(config::var_or("TASK_TTL_SECS", 60 * 60)?),
+            max_finished: config::var_or("TASK_RETENTION_MAX", 1000)?,
+            sweep_interval: Duration::from_secs(config::var_or("TASK_SWEEP_INTERVAL_SECS", 60)?),
+        })
+    }
+}
+
+/// The query parameters used to select tasks for listing or removal
+#[derive(Deserialize, Debug, Default)]
+pub struct TaskFilter {
+    /// One of `in_progress`, `cancelled` or `completed`
+    pub status: Option<String>,
+    /// The endpoint that created the task, e.g. `search` or `add`
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// The ID of the user that created the task, always set for non-admins
+    pub owner: Option<String>,
+    /// Only tasks created at or after this unix timestamp
+    pub since: Option<u64>,
+    /// Only tasks created before this unix timestamp
+    pub until: Option<u64>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, info: &TaskInfo) -> bool {
+        self.status
+            .as_ref()
+            .map(|s| s == info.task.status())
+            .unwrap_or(true)
+            && self.kind.as_ref().map(|k| k == &info.kind).unwrap_or(true)
+            && self
+                .owner
+                .as_ref()
+                .map(|o| o == &info.owner)
+                .unwrap_or(true)
+            && self.since.map(|t| info.created_at >= t).unwrap_or(true)
+            && self.until.map(|t| info.created_at < t).unwrap_or(true)
+    }
 }
 
 /// An abortable task created from a future that results in a json value
@@ -33,6 +135,9 @@ pub enum Task {
     InProgress {
         #[serde(skip)]
         abort_handle: Option<AbortHandle>,
+        /// The stage the task last reported, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        progress: Option<String>,
     },
     Cancelled,
     Completed {
@@ -40,89 +145,528 @@ pub enum Task {
     },
 }
 
+impl Task {
+    /// Whether the task has reached a state it will never leave.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, Task::InProgress { .. })
+    }
+
+    /// The name of the task's state, as used by `TaskFilter`.
+    pub fn status(&self) -> &'static str {
+        match self {
+            Task::InProgress { .. } => "in_progress",
+            Task::Cancelled => "cancelled",
+            Task::Completed { .. } => "completed",
+        }
+    }
+}
+
+/// The current unix timestamp in seconds
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A state transition or progress update of a task, as pushed to event stream subscribers
+#[derive(Serialize, Clone, Debug, Deserialize)]
+pub struct TaskEvent {
+    pub id: u32,
+    /// The ID of the user that created the task
+    pub owner: String,
+    pub task: Task,
+}
+
+impl From<&TaskInfo> for TaskEvent {
+    fn from(info: &TaskInfo) -> Self {
+        Self {
+            id: info.id,
+            owner: info.owner.clone(),
+            task: info.task.clone(),
+        }
+    }
+}
+
+/// Reports the stage of the daemon task running the current future.
+/// Does nothing when called outside of a daemon task.
+pub async fn report_progress(progress: &str) {
+    if let Ok((daemon, id)) = CURRENT_TASK.try_with(|current| current.clone()) {
+        daemon.set_task_progress(id, progress).await;
+    }
+}
+
 impl Daemon {
     pub fn new(client: LocalClient) -> Self {
         Self {
             client,
             tasks: Default::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
-    /// Adds a new task and spawns the future, returns the ID to query the task with.
-    pub async fn new_task<F: Future<Output = Value> + 'static>(&self, f: F) -> u32 {
+    /// Adds a new task of the given kind on behalf of the owner and spawns the future,
+    /// returns the ID to query the task with.
+    pub async fn new_task<F: Future<Output = Value> + 'static>(
+        &self,
+        kind: &str,
+        owner: &str,
+        f: F,
+    ) -> u32 {
         let id = TASK_ID.fetch_add(1, Ordering::Relaxed);
         let (fut, abort_handle) = {
             let this = self.clone();
+            let f = CURRENT_TASK.scope((self.clone(), id), f);
             abortable(f.then(move |v| async move { this.set_task_completed(id, v).await }))
         };
 
-        tokio::task::spawn_local(fut);
-
-        self.tasks.lock().await.insert(
+        let now = unix_time();
+        let info = TaskInfo {
             id,
-            Task::InProgress {
+            kind: kind.to_string(),
+            owner: owner.to_string(),
+            created_at: now,
+            updated_at: now,
+            task: Task::InProgress {
                 abort_handle: Some(abort_handle),
+                progress: None,
             },
-        );
+        };
+        let mut tasks = self.tasks.lock().await;
+        self.notify(&info);
+        tasks.insert(id, info);
+        drop(tasks);
+
+        tokio::task::spawn_local(fut);
 
         id
     }
 
-    /// Marks the task complete with its resulting data. Ignores if the task was cancelled.
+    /// Runs the future as a task of the user, see `new_task`. Maps the future's result into the
+    /// data or a serialized error, and saves the input of failed tasks in `failed_tasks.ndjson`
+    /// along with the path of the request that created them.
+    pub(crate) async fn spawn_task<
+        In: Serialize + Clone + 'static,
+        Out: Serialize,
+        F: FnOnce(LocalClient, In) -> Fut,
+        Fut: Future<Output = Result<Out>> + 'static,
+    >(
+        &self,
+        kind: &str,
+        path: &str,
+        user: ApiUser,
+        input: In,
+        res: F,
+    ) -> u32 {
+        let path = path.to_string();
+        self.new_task(
+            kind,
+            &user.id,
+            res(self.client_for(user.clone()), input.clone()).map(move |res| match res {
+                Ok(t) => to_value(t).unwrap(),
+                Err(e) => {
+                    error!("daemon error: {e}");
+                    let err = json!({
+                        "error": e.to_string(),
+                        "backtrace": e.chain().map(|err| err.to_string()).collect::<Vec<_>>(),
+                        "input": to_value(input).unwrap(),
+                        "path": path
+                    });
+
+                    // save the failed task for possible replay
+                    let mut failed_tasks_file = std::fs::File::options()
+                        .append(true)
+                        .create(true)
+                        .open("failed_tasks.ndjson")
+                        .unwrap();
+                    writeln!(
+                        &mut failed_tasks_file,
+                        "{}",
+                        serde_json::to_string(&err).unwrap()
+                    )
+                    .unwrap();
+
+                    err
+                }
+            }),
+        )
+        .await
+    }
+
+    /// The daemon's client acting on behalf of the user.
+    pub(crate) fn client_for(&self, user: ApiUser) -> LocalClient {
+        self.client.as_user(user)
+    }
+
+    /// Sends a task's state to event stream subscribers.
+    fn notify(&self, info: &TaskInfo) {
+        // sending only fails when nobody is subscribed
+        let _ = self.events.send(TaskEvent::from(info));
+    }
+
+    /// Stores the new state of a task and notifies event stream subscribers. Ignores unknown tasks.
+    fn update_task(&self, tasks: &mut HashMap<u32, TaskInfo>, id: u32, task: Task) {
+        if let Some(info) = tasks.get_mut(&id) {
+            info.updated_at = unix_time();
+            info.task = task;
+            self.notify(info);
+        }
+    }
+
+    /// Records the stage of a task. Ignores if the task isn't in progress.
+    pub async fn set_task_progress(&self, id: u32, stage: &str) {
+        let mut tasks = self.tasks.lock().await;
+
+        if let Some(Task::InProgress { abort_handle, .. }) = tasks.get(&id).map(|info| &info.task) {
+            let task = Task::InProgress {
+                abort_handle: abort_handle.clone(),
+                progress: Some(stage.to_string()),
+            };
+            self.update_task(&mut tasks, id, task);
+        }
+    }
+
+    /// Marks the task complete with its resulting data. Ignores if the task isn't in progress.
     pub async fn set_task_completed(&self, id: u32, data: Value) {
         let mut tasks = self.tasks.lock().await;
 
         if tasks
             .get(&id)
-            .map(|t| !matches!(t, Task::Cancelled))
-            .unwrap_or(true)
+            .map(|info| !info.task.is_finished())
+            .unwrap_or(false)
         {
-            tasks.insert(id, Task::Completed { data });
+            self.update_task(&mut tasks, id, Task::Completed { data });
         }
     }
 
     /// Cancels the task by aborting the future, ignores if task isn't in progress.
     pub async fn cancel_task(&self, id: u32) {
-        if let Some(t) = self.tasks.lock().await.get_mut(&id) {
-            if let Task::InProgress { abort_handle } = t {
-                abort_handle.as_ref().unwrap().abort();
-                *t = Task::Cancelled;
-            }
+        let mut tasks = self.tasks.lock().await;
+
+        if let Some(Task::InProgress { abort_handle, .. }) = tasks.get(&id).map(|info| &info.task) {
+            abort_handle.as_ref().unwrap().abort();
+            self.update_task(&mut tasks, id, Task::Cancelled);
         }
     }
 
     /// Query for the task.
     pub async fn get_task(&self, id: u32) -> Option<Task> {
-        self.tasks.lock().await.get(&id).cloned()
+        self.tasks
+            .lock()
+            .await
+            .get(&id)
+            .map(|info| info.task.clone())
+    }
+
+    /// Query for the task if the user may access it, which only admins and the task's owner may.
+    pub async fn get_task_for(&self, id: u32, user: &ApiUser) -> Option<TaskInfo> {
+        self.tasks
+            .lock()
+            .await
+            .get(&id)
+            .filter(|info| user.admin || info.owner == user.id)
+            .cloned()
+    }
+
+    /// Waits up to `timeout` for the task to finish, returns `None` if it's still in progress
+    /// or doesn't exist.
+    pub async fn wait_for_task(&self, id: u32, timeout: Duration) -> Option<Task> {
+        let mut events = Box::pin(self.task_events(Some(id), None).await?);
+        let finished = async move {
+            while let Some(event) = events.next().await {
+                if event.task.is_finished() {
+                    return Some(event.task);
+                }
+            }
+            None
+        };
+        tokio::time::timeout(timeout, finished).await.ok().flatten()
+    }
+
+    /// Lists the tasks matching the filter, ordered by ID.
+    pub async fn list_tasks(&self, filter: &TaskFilter) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .await
+            .values()
+            .filter(|info| filter.matches(info))
+            .cloned()
+            .collect();
+        tasks.sort_by_key(|info| info.id);
+        tasks
+    }
+
+    /// Removes the finished tasks matching the filter, returns how many were removed.
+    pub async fn remove_tasks(&self, filter: &TaskFilter) -> usize {
+        let mut tasks = self.tasks.lock().await;
+        let before = tasks.len();
+        tasks.retain(|_, info| !(info.task.is_finished() && filter.matches(info)));
+        before - tasks.len()
+    }
+
+    /// Evicts finished tasks that outlived the retention TTL, then the oldest finished tasks
+    /// beyond the retention maximum. Returns how many were evicted.
+    pub async fn evict_tasks(&self, retention: &TaskRetention) -> usize {
+        let mut tasks = self.tasks.lock().await;
+        let before = tasks.len();
+
+        let expiry = unix_time().saturating_sub(retention.ttl.as_secs());
+        tasks.retain(|_, info| !(info.task.is_finished() && info.updated_at < expiry));
+
+        let mut finished: Vec<_> = tasks
+            .values()
+            .filter(|info| info.task.is_finished())
+            .map(|info| (info.updated_at, info.id))
+            .collect();
+        if finished.len() > retention.max_finished {
+            finished.sort_unstable();
+            for (_, id) in &finished[..finished.len() - retention.max_finished] {
+                tasks.remove(id);
+            }
+        }
+
+        before - tasks.len()
+    }
+
+    /// Periodically evicts old finished tasks, runs forever.
+    pub async fn sweep_tasks(self, retention: TaskRetention) {
+        let mut interval = tokio::time::interval(retention.sweep_interval);
+        loop {
+            interval.tick().await;
+            let evicted = self.evict_tasks(&retention).await;
+            if evicted > 0 {
+                info!("evicted {evicted} finished tasks");
+            }
+        }
+    }
+
+    /// Periodically checks the original links of all entries, runs forever unless the monitor is disabled.
+    pub async fn monitor_links(self, monitor: LinkMonitor) {
+        if monitor.interval.is_zero() {
+            return;
+        }
+        let mut interval = tokio::time::interval(monitor.interval);
+        loop {
+            interval.tick().await;
+            match health::check_links(&self.client, monitor.interval, monitor.concurrency).await {
+                Ok(report) if report.checked > 0 => info!(
+                    "checked {} original links, {} gone of which {} newly, {} failed",
+                    report.checked,
+                    report.gone,
+                    report.newly_gone.len(),
+                    report.error
+                ),
+                Ok(_) => {}
+                Err(e) => error!("failed to check original links: {e:#}"),
+            }
+        }
+    }
+
+    /// Streams the events of one task, or of all tasks if `id` is `None`.
+    /// If `owner` is given, only streams the events of tasks created by that user.
+    ///
+    /// The stream starts with the current state of the requested task (or of every task in progress),
+    /// and a stream for one task ends after that task finishes.
+    /// Returns `None` if the requested task doesn't exist.
+    pub async fn task_events(
+        &self,
+        id: Option<u32>,
+        owner: Option<String>,
+    ) -> Option<impl Stream<Item = TaskEvent>> {
+        // subscribe before taking the snapshot so no transition falls in between
+        let receiver = self.events.subscribe();
+        let snapshot: Vec<_> = {
+            let tasks = self.tasks.lock().await;
+            match id {
+                Some(id) => vec![TaskEvent::from(tasks.get(&id)?)],
+                None => {
+                    let mut snapshot: Vec<_> = tasks
+                        .values()
+                        .filter(|info| !info.task.is_finished())
+                        .map(TaskEvent::from)
+                        .collect();
+                    snapshot.sort_by_key(|event| event.id);
+                    snapshot
+                }
+            }
+        };
+
+        let updates = stream::unfold(
+            (self.clone(), receiver, owner),
+            move |(daemon, mut receiver, owner)| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event)
+                            if id.map(|id| id == event.id).unwrap_or(true)
+                                && owner.as_ref().map(|o| o == &event.owner).unwrap_or(true) =>
+                        {
+                            return Some((event, (daemon, receiver, owner)))
+                        }
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("task event stream lagged behind by {skipped} events");
+
+                            // the skipped events may have included the final state of the task
+                            if let Some(id) = id {
+                                let event = TaskEvent::from(daemon.tasks.lock().await.get(&id)?);
+                                return Some((event, (daemon, receiver, owner)));
+                            }
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        );
+
+        // stop right after the final event instead of waiting on the next one
+        let events = Box::pin(stream::iter(snapshot).chain(updates));
+        Some(stream::unfold(
+            (events, false),
+            move |(mut events, finished)| async move {
+                if finished {
+                    return None;
+                }
+                let event = events.next().await?;
+                let finished = id.is_some() && event.task.is_finished();
+                Some((event, (events, finished)))
+            },
+        ))
+    }
+}
+
+/// How daemon requests are authenticated and rate limited. Clones are referenced counted.
+#[derive(Clone)]
+pub(crate) struct Access {
+    pub(crate) tokens: TokenStore,
+    limiter: RateLimiter,
+    /// Whether every request acts as the local admin
+    pub(crate) auth_disabled: bool,
+}
+
+impl Access {
+    /// Returns the user that made the request, or the error to respond with.
+    /// Requests without valid credentials get the error made by `unauthorized`.
+    pub(crate) fn check(
+        &self,
+        req: &dev::ServiceRequest,
+        unauthorized: fn() -> Error,
+    ) -> Result<ApiUser, Error> {
+        let user = match self.auth_disabled {
+            true => Ok(Some(ApiUser::local())),
+            false => self.tokens.authenticate_request(req),
+        };
+        match user {
+            Ok(Some(user)) => match self.limiter.check(&user.id) {
+                Ok(()) => Ok(user),
+                Err(retry_after) => Err(quota::too_many_requests(retry_after)),
+            },
+            Ok(None) => Err(unauthorized()),
+            Err(e) => {
+                error!("failed to authenticate request: {e:#}");
+                Err(error::ErrorInternalServerError(
+                    "failed to authenticate request",
+                ))
+            }
+        }
     }
 }
 
 /// Starts a daemon from the given `Client`
 pub async fn run(client: LocalClient) -> Result<()> {
     let daemon = Daemon::new(client);
-    HttpServer::new(move || {
+    tokio::spawn(daemon.clone().sweep_tasks(TaskRetention::from_env()?));
+    // checks run downloaders, which can't be moved to another thread
+    let monitor = daemon.clone().monitor_links(LinkMonitor::from_env()?);
+
+    let access = Access {
+        tokens: TokenStore::from_env()?,
+        limiter: RateLimiter::from_env()?,
+        auth_disabled: config::var_or("AUTH_DISABLED", false)?,
+    };
+    if access.auth_disabled {
+        warn!("authentication is disabled, anyone who can reach the daemon has full access");
+    } else if access.tokens.list()?.is_empty() {
+        warn!("no API tokens exist, create one with `backend token create`");
+    }
+
+    let server = HttpServer::new(move || {
         use endpoints::*;
 
+        let api_access = access.clone();
+        let ui_access = access.clone();
         App::new()
             .service(
                 web::scope("/api/v0")
+                    .wrap_fn(
+                        move |req, srv| match api_access.check(&req, auth::unauthorized) {
+                            Ok(user) => {
+                                req.extensions_mut().insert(user);
+                                Either::Left(srv.call(req))
+                            }
+                            Err(e) => Either::Right(ready(Err(e))),
+                        },
+                    )
                     .service(search_endpoint)
                     .service(add_endpoint)
-                    .service(task_endpoint),
+                    .service(get_entry_endpoint)
+                    .service(update_entry_endpoint)
+                    // before the entry file route, which would take `versions` for a file name
+                    .service(versions_endpoint)
+                    .service(entry_file_endpoint)
+                    .service(entry_media_item_endpoint)
+                    .service(entry_sidecar_endpoint)
+                    .service(refresh_entry_endpoint)
+                    .service(version_file_endpoint)
+                    .service(usage_endpoint)
+                    .service(link_report_endpoint)
+                    .service(task_endpoint)
+                    .service(task_events_endpoint)
+                    .service(tasks_endpoint)
+                    .service(all_task_events_endpoint),
+            )
+            .service(ui::style_sheet)
+            .service(ui::login_page)
+            .service(ui::login_action)
+            .service(ui::logout_action)
+            .service(
+                web::scope("")
+                    .wrap_fn(
+                        move |req, srv| match ui_access.check(&req, ui::login_required) {
+                            Ok(user) => {
+                                req.extensions_mut().insert(user);
+                                Either::Left(srv.call(req))
+                            }
+                            Err(e) => Either::Right(ready(Err(e))),
+                        },
+                    )
+                    .service(ui::search_page)
+                    .service(ui::add_page)
+                    .service(ui::add_action)
+                    .service(ui::originals_page)
+                    .service(ui::tasks_page)
+                    .service(ui::task_page)
+                    .service(ui::cancel_task_action)
+                    .service(ui::entry_page)
+                    .service(ui::update_entry_action)
+                    .service(ui::refresh_entry_action)
+                    .service(ui::media_endpoint)
+                    .service(ui::media_item_endpoint)
+                    .service(ui::sidecar_endpoint)
+                    .service(ui::version_file_endpoint),
             )
             .app_data(web::Data::new(daemon.clone()))
+            .app_data(web::Data::new(access.clone()))
     })
     .bind(("0.0.0.0", 5003))?
-    .run()
-    .await?;
-    Ok(())
-}
+    .run();
 
-/// The input data for the add link endpoint
-#[derive(Deserialize, Serialize, Clone)]
-pub struct AddLink {
-    pub link: String,
-    pub description: String,
+    // the monitor never finishes while it's enabled, so it's dropped once the server stops
+    let monitor = monitor.then(|()| future::pending::<Infallible>());
+    tokio::select! {
+        result = server => Ok(result?),
+        never = monitor => match never {},
+    }
 }
 
 /// The API endpoints
@@ -130,8 +674,15 @@ pub mod endpoints {
     use actix_web::{http::Method, web, *};
     use serde_json::json;
 
-    use super::{to_responder, AddLink};
-    use crate::{api::ClientApi, daemon::Daemon};
+    use serde::Deserialize;
+
+    use super::{media_response, read_response, to_event_stream, to_responder, TaskFilter};
+    use crate::{
+        api::{AddLink, ClientApi, EntryUpdate},
+        auth::ApiUser,
+        daemon::Daemon,
+        health::OriginalStatus,
+    };
 
     #[post("/search")]
     async fn search_endpoint(
@@ -139,8 +690,8 @@ pub mod endpoints {
         daemon: web::Data<Daemon>,
         req: HttpRequest,
     ) -> impl Responder {
-        to_responder(&daemon, req, name, |daemon, name| async move {
-            daemon.client.search(&name).await
+        to_responder(&daemon, req, name, |client, name| async move {
+            client.search(&name).await
         })
         .await
     }
@@ -155,27 +706,181 @@ pub mod endpoints {
             &daemon,
             req,
             input.into_inner(),
-            |daemon, input| async move {
-                daemon
-                    .client
-                    .add_link(&input.link, &input.description)
-                    .await
-            },
+            |client, input| async move { client.add_link(&input).await },
+        )
+        .await
+    }
+
+    #[get("/entries/{entry_id}", name = "get_entry")]
+    async fn get_entry_endpoint(
+        entry_id: web::Path<String>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+    ) -> impl Responder {
+        let client = daemon.client_for(user.into_inner());
+        read_response(client.get_entry(&entry_id).await)
+    }
+
+    #[patch("/entries/{entry_id}", name = "update_entry")]
+    async fn update_entry_endpoint(
+        entry_id: web::Path<String>,
+        update: web::Json<EntryUpdate>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        to_responder(
+            &daemon,
+            req,
+            (entry_id.into_inner(), update.into_inner()),
+            |client, (id, update)| async move { client.update_entry(&id, &update).await },
+        )
+        .await
+    }
+
+    /// Serves the `media`, `thumbnail`, `preview` or `original` of an entry
+    #[get("/entries/{entry_id}/{file}")]
+    async fn entry_file_endpoint(
+        path: web::Path<(String, String)>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let (entry_id, file) = path.into_inner();
+        media_response(
+            &daemon.client_for(user.into_inner()),
+            &entry_id,
+            &file,
+            &req,
+        )
+        .await
+    }
+
+    /// Serves a sidecar file of an entry by its name
+    #[get("/entries/{entry_id}/sidecars/{name}")]
+    async fn entry_sidecar_endpoint(
+        path: web::Path<(String, String)>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let (entry_id, name) = path.into_inner();
+        let file = format!("sidecars/{name}");
+        media_response(
+            &daemon.client_for(user.into_inner()),
+            &entry_id,
+            &file,
+            &req,
+        )
+        .await
+    }
+
+    /// Serves an item of an entry's media list by its position
+    #[get("/entries/{entry_id}/media/{index}")]
+    async fn entry_media_item_endpoint(
+        path: web::Path<(String, usize)>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let (entry_id, index) = path.into_inner();
+        let file = format!("media/{index}");
+        media_response(
+            &daemon.client_for(user.into_inner()),
+            &entry_id,
+            &file,
+            &req,
+        )
+        .await
+    }
+
+    #[post("/entries/{entry_id}/refresh", name = "refresh_entry")]
+    async fn refresh_entry_endpoint(
+        entry_id: web::Path<String>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        to_responder(
+            &daemon,
+            req,
+            entry_id.into_inner(),
+            |client, id| async move { client.refresh_entry(&id).await },
+        )
+        .await
+    }
+
+    #[get("/entries/{entry_id}/versions", name = "versions")]
+    async fn versions_endpoint(
+        entry_id: web::Path<String>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+    ) -> impl Responder {
+        let client = daemon.client_for(user.into_inner());
+        read_response(client.versions(&entry_id).await)
+    }
+
+    /// Serves a file of a version of an entry's content, like `media`, `media/1` or `sidecars/{name}`
+    #[get("/entries/{entry_id}/versions/{version}/{file:.*}")]
+    async fn version_file_endpoint(
+        path: web::Path<(String, usize, String)>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let (entry_id, version, file) = path.into_inner();
+        let file = format!("versions/{version}/{file}");
+        media_response(
+            &daemon.client_for(user.into_inner()),
+            &entry_id,
+            &file,
+            &req,
+        )
+        .await
+    }
+
+    #[get("/usage")]
+    async fn usage_endpoint(daemon: web::Data<Daemon>, req: HttpRequest) -> impl Responder {
+        to_responder(
+            &daemon,
+            req,
+            (),
+            |client, _| async move { client.usage().await },
         )
         .await
     }
 
+    #[derive(Deserialize)]
+    struct LinkReportQuery {
+        /// The status of the entries listed, `gone` by default
+        status: Option<OriginalStatus>,
+    }
+
+    /// Counts the entries by the status of their original link and lists those with the requested status
+    #[get("/originals", name = "link_report")]
+    async fn link_report_endpoint(
+        query: web::Query<LinkReportQuery>,
+        daemon: web::Data<Daemon>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let status = query.status.unwrap_or(OriginalStatus::Gone);
+        to_responder(&daemon, req, status, |client, status| async move {
+            client.link_report(status).await
+        })
+        .await
+    }
+
     #[route("/task/{task_id}", method = "GET", method = "DELETE")]
     async fn task_endpoint(
         task_id: web::Path<u32>,
         method: Method,
+        user: web::ReqData<ApiUser>,
         daemon: web::Data<Daemon>,
     ) -> impl Responder {
+        let Some(info) = daemon.get_task_for(*task_id, &user).await else {
+            return HttpResponse::BadRequest().json(json!({"error": "unknown task"}));
+        };
+
         match method {
-            _ if method == Method::GET => match daemon.get_task(*task_id).await {
-                Some(t) => HttpResponse::Ok().json(t),
-                None => HttpResponse::BadRequest().json(json!({"error": "unknown task"})),
-            },
+            _ if method == Method::GET => HttpResponse::Ok().json(info.task),
             _ if method == Method::DELETE => {
                 daemon.cancel_task(*task_id).await;
                 HttpResponse::Ok().finish()
@@ -183,17 +888,310 @@ pub mod endpoints {
             _ => HttpResponse::BadRequest().finish(),
         }
     }
+
+    #[get("/task/{task_id}/events")]
+    async fn task_events_endpoint(
+        task_id: web::Path<u32>,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+    ) -> impl Responder {
+        if daemon.get_task_for(*task_id, &user).await.is_none() {
+            return HttpResponse::BadRequest().json(json!({"error": "unknown task"}));
+        }
+
+        match daemon.task_events(Some(*task_id), None).await {
+            Some(events) => to_event_stream(events),
+            None => HttpResponse::BadRequest().json(json!({"error": "unknown task"})),
+        }
+    }
+
+    #[route("/tasks", method = "GET", method = "DELETE")]
+    async fn tasks_endpoint(
+        filter: web::Query<TaskFilter>,
+        method: Method,
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+    ) -> impl Responder {
+        let mut filter = filter.into_inner();
+        if !user.admin {
+            filter.owner = Some(user.id.clone());
+        }
+
+        match method {
+            _ if method == Method::GET => HttpResponse::Ok().json(daemon.list_tasks(&filter).await),
+            _ if method == Method::DELETE => {
+                let removed = daemon.remove_tasks(&filter).await;
+                HttpResponse::Ok().json(json!({ "removed": removed }))
+            }
+            _ => HttpResponse::BadRequest().finish(),
+        }
+    }
+
+    #[get("/tasks/events")]
+    async fn all_task_events_endpoint(
+        user: web::ReqData<ApiUser>,
+        daemon: web::Data<Daemon>,
+    ) -> impl Responder {
+        let owner = (!user.admin).then(|| user.id.clone());
+        to_event_stream(daemon.task_events(None, owner).await.unwrap())
+    }
+}
+
+/// Responds with the task events as a stream of server-sent events.
+/// Each event is a `data:` line holding a json `TaskEvent`, idle streams get a keep-alive comment.
+fn to_event_stream(events: impl Stream<Item = TaskEvent> + 'static) -> HttpResponse {
+    let events =
+        events.map(|event| format!("data: {}\n\n", serde_json::to_string(&event).unwrap()));
+    let body = stream::unfold(Box::pin(events), |mut events| async move {
+        let chunk = match tokio::time::timeout(KEEP_ALIVE, events.next()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return None,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        Some((Ok::<_, Error>(web::Bytes::from(chunk)), events))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("cache-control", "no-cache"))
+        .streaming(body)
+}
+
+/// Responds with the result of reading an entry directly rather than through a task, since reads are
+/// quick and there is nothing to retry when they fail. Failures are a 404 Not Found, as in `media_response`.
+fn read_response<T: Serialize>(result: Result<T>) -> HttpResponse {
+    match result {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(e) => HttpResponse::NotFound().json(json!({"error": format!("{e:#}")})),
+    }
+}
+
+/// Streams a file of an entry the client's user may see: its main `media`, the `thumbnail` or
+/// `preview` generated for it, the `original` it was converted from if kept, the item at an index of its media list, as in `media/2`, or a
+/// sidecar file by name, as in `sidecars/video.en.vtt`. Files of a previous version of the entry's content are prefixed with
+/// the version, as in `versions/1/media`. The content type is the one recorded when the file was stored, or else
+/// sniffed from the first bytes.
+///
+/// Supports single byte ranges, so browsers can seek within videos. Files are shown inline,
+/// unless the request has a `?download` query parameter.
+pub(crate) async fn media_response(
+    client: &LocalClient,
+    id: &str,
+    file: &str,
+    req: &HttpRequest,
+) -> HttpResponse {
+    let entry = match client.get_entry(id).await {
+        Ok(entry) => entry,
+        Err(e) => return HttpResponse::NotFound().json(json!({"error": e.to_string()})),
+    };
+    let (name, payload, file) = match file
+        .strip_prefix("versions/")
+        .and_then(|file| file.split_once('/'))
+    {
+        Some((version, file)) => {
+            let content = version.parse::<usize>().ok().and_then(|version| {
+                EntryVersion::of_payload(&entry.payload)
+                    .into_iter()
+                    .find(|v| v.version == version)
+            });
+            let Some(content) = content else {
+                return HttpResponse::NotFound()
+                    .json(json!({"error": format!("entry has no version {version}")}));
+            };
+            (format!("{id}-v{version}"), content.content, file)
+        }
+        None => (id.to_string(), entry.payload, file),
+    };
+
+    // generated files are recorded like the media itself, under their own key
+    let index = file
+        .strip_prefix("media/")
+        .and_then(|index| index.parse::<usize>().ok());
+    let sidecar = file.strip_prefix("sidecars/");
+    let stored = match (file, index, sidecar) {
+        ("media", _, _) => &payload,
+        ("thumbnail" | "preview", _, _) => &payload[file],
+        ("original", _, _) => &payload["media"][0]["original"],
+        (_, Some(index), _) => &payload["media"][index],
+        (_, _, Some(name)) => payload["sidecars"]["files"]
+            .as_array()
+            .and_then(|files| files.iter().find(|f| f["name"] == name))
+            .unwrap_or(&Value::Null),
+        _ => {
+            return HttpResponse::NotFound().json(json!({"error": format!("no such file {file}")}))
+        }
+    };
+    let Some(cid) = stored["cid"].as_str() else {
+        return HttpResponse::NotFound().json(json!({"error": format!("entry has no {file}")}));
+    };
+
+    let content_type = match stored["content_type"].as_str() {
+        Some(content_type) => Ok(content_type),
+        None => client
+            .storage
+            .cat_range(cid, 0, 16)
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .map(|head| storage::sniff_content_type(&head).0),
+    };
+    let (content_type, size) = match (content_type, client.storage.size(cid).await) {
+        (Ok(content_type), Ok(size)) => (content_type, size),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("failed to fetch {file} of entry {id}: {e:#}");
+            return HttpResponse::BadGateway()
+                .json(json!({"error": format!("failed to fetch {file}")}));
+        }
+    };
+
+    let range = req
+        .headers()
+        .get(http::header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        // ranges in unknown units, invalid and multiple ranges are ignored, the whole file is served instead
+        .and_then(|range| parse_range(range, size));
+    let (mut response, offset, length) = match range {
+        Some(ByteRange::Satisfiable(start, end)) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((
+                http::header::CONTENT_RANGE,
+                format!("bytes {start}-{end}/{size}"),
+            ));
+            (response, start, end - start + 1)
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            return HttpResponse::RangeNotSatisfiable()
+                .insert_header((http::header::CONTENT_RANGE, format!("bytes */{size}")))
+                .finish()
+        }
+        None => (HttpResponse::Ok(), 0, size),
+    };
+
+    let disposition = match req
+        .query_string()
+        .split('&')
+        .any(|q| q == "download" || q.starts_with("download="))
+    {
+        true => "attachment",
+        false => "inline",
+    };
+    let extension = storage::extension_for(content_type);
+    let filename = match (file, sidecar) {
+        ("media", _) => format!("{name}.{extension}"),
+        // sidecars keep the names yt-dlp gave them
+        (_, Some(name)) => name.replace(['"', '\\'], "_"),
+        (file, _) => format!("{name}-{}.{extension}", file.replace('/', "-")),
+    };
+
+    let body = client
+        .storage
+        .cat_range(cid, offset, length)
+        .map_err(error::ErrorInternalServerError);
+    response
+        .content_type(content_type)
+        .insert_header((http::header::ACCEPT_RANGES, "bytes"))
+        // archived pages must not run scripts with the daemon's origin
+        .insert_header((http::header::CONTENT_SECURITY_POLICY, "sandbox"))
+        .insert_header((
+            http::header::CONTENT_DISPOSITION,
+            format!("{disposition}; filename=\"{filename}\""),
+        ))
+        .no_chunking(length)
+        .streaming(body)
+}
+
+/// A `Range` header parsed for content of a certain size
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// The first and last byte requested
+    Satisfiable(u64, u64),
+    /// A valid range that lies beyond the end of the content
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for content of the given size. Returns `None` if the header is invalid,
+/// or if multiple ranges are requested since those aren't supported, as such headers are to be ignored.
+fn parse_range(range: &str, size: u64) -> Option<ByteRange> {
+    let range = range.strip_prefix("bytes=")?.trim();
+    if range.contains(',') {
+        return None;
+    }
+    let position = |s: &str| match s.bytes().all(|b| b.is_ascii_digit()) {
+        true => s.parse::<u64>().ok(),
+        false => None,
+    };
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // the last `suffix` bytes
+        ("", suffix) => match position(suffix)? {
+            0 => return Some(ByteRange::Unsatisfiable),
+            suffix => (size.saturating_sub(suffix), size.checked_sub(1)),
+        },
+        (start, "") => (position(start)?, size.checked_sub(1)),
+        (start, end) => {
+            let (start, end) = (position(start)?, position(end)?);
+            if start > end {
+                return None;
+            }
+            (start, size.checked_sub(1).map(|last| end.min(last)))
+        }
+    };
+    match end {
+        Some(end) if start <= end => Some(ByteRange::Satisfiable(start, end)),
+        _ => Some(ByteRange::Unsatisfiable),
+    }
+}
+
+/// Parses a duration such as `30s`, `500ms`, `2m` or a plain number of seconds.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number.parse().ok()?;
+    match unit.trim() {
+        "" | "s" => Some(Duration::from_secs(number)),
+        "ms" => Some(Duration::from_millis(number)),
+        "m" => number.checked_mul(60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Reads how long the client is willing to wait for the task's result, from either a `?wait=30s`
+/// query parameter or a `Prefer: wait=30` header. Capped at `MAX_WAIT`.
+fn requested_wait(req: &HttpRequest) -> Option<Duration> {
+    #[derive(Deserialize)]
+    struct WaitQuery {
+        wait: Option<String>,
+    }
+
+    let from_query = web::Query::<WaitQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.wait.as_deref().and_then(parse_duration));
+    let from_header = || {
+        req.headers()
+            .get_all("prefer")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(|preference| preference.trim().strip_prefix("wait="))
+            .and_then(parse_duration)
+    };
+
+    from_query
+        .or_else(from_header)
+        .map(|wait| wait.min(MAX_WAIT))
 }
 
 /// Transforms a future into a task and responds with a 202 Accepted that contains
 /// a Location header for the query task endpoint. Maps the future's result into the
 /// data or a serialized error.
 ///
+/// If the client asked to wait (see `requested_wait`) and the task finishes in time,
+/// responds with a 200 OK that contains the finished task instead.
+///
 /// If the task fails, the input data is saved in a ndjson file `failed_tasks.ndjson` for possible retries.
 async fn to_responder<
     In: Serialize + Clone + 'static,
     Out: Serialize,
-    F: FnOnce(Daemon, In) -> Fut,
+    F: FnOnce(LocalClient, In) -> Fut,
     Fut: Future<Output = Result<Out>> + 'static,
 >(
     daemon: &Daemon,
@@ -201,39 +1199,142 @@ async fn to_responder<
     input: In,
     res: F,
 ) -> impl Responder {
-    let task_id = daemon
-        .new_task(
-            res(daemon.clone(), input.clone()).map(move |res| match res {
-                Ok(t) => to_value(t).unwrap(),
-                Err(e) => {
-                    error!("daemon error: {e}");
-                    let err = json!({
-                        "error": e.to_string(),
-                        "backtrace": e.chain().map(|err| err.to_string()).collect::<Vec<_>>(),
-                        "input": to_value(input).unwrap(),
-                        "path": req.path()
-                    });
+    // tasks are named after their route, or else the endpoint that created them
+    let kind = req
+        .match_name()
+        .or_else(|| req.path().rsplit('/').next())
+        .unwrap_or_default()
+        .to_string();
+    let wait = requested_wait(&req);
+    let user = req
+        .extensions()
+        .get::<ApiUser>()
+        .cloned()
+        .unwrap_or_else(ApiUser::local);
+    let task_id = daemon.spawn_task(&kind, req.path(), user, input, res).await;
 
-                    // save the failed task for possible replay
-                    let mut failed_tasks_file = std::fs::File::options()
-                        .append(true)
-                        .create(true)
-                        .open("failed_tasks.ndjson")
-                        .unwrap();
-                    writeln!(
-                        &mut failed_tasks_file,
-                        "{}",
-                        serde_json::to_string(&err).unwrap()
-                    )
-                    .unwrap();
+    let location = ("location", format!("/api/v0/task/{task_id}"));
+    if let Some(wait) = wait {
+        if let Some(task) = daemon.wait_for_task(task_id, wait).await {
+            return HttpResponse::Ok().insert_header(location).json(task);
+        }
+    }
 
-                    err
-                }
-            }),
-        )
-        .await;
+    HttpResponse::Accepted().insert_header(location).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::test::TestRequest;
+
+    use super::{parse_duration, parse_range, requested_wait, ByteRange::*, MAX_WAIT};
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 30s "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("-5"), None);
+        assert_eq!(parse_duration("2h"), None);
+        assert_eq!(parse_duration("1.5s"), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert_eq!(parse_duration(&format!("{}m", u64::MAX)), None);
+        assert_eq!(parse_duration("99999999999999999999999"), None);
+    }
 
-    HttpResponse::Accepted()
-        .insert_header(("location", format!("/api/v0/task/{task_id}")))
-        .finish()
+    #[test]
+    fn reads_wait_from_query_or_header() {
+        let req = TestRequest::with_uri("/api/v0/add?wait=10s").to_http_request();
+        assert_eq!(requested_wait(&req), Some(Duration::from_secs(10)));
+
+        let req = TestRequest::default()
+            .insert_header(("prefer", "respond-async, wait=20"))
+            .to_http_request();
+        assert_eq!(requested_wait(&req), Some(Duration::from_secs(20)));
+
+        // the query parameter wins over the header
+        let req = TestRequest::with_uri("/api/v0/add?wait=5")
+            .insert_header(("prefer", "wait=20"))
+            .to_http_request();
+        assert_eq!(requested_wait(&req), Some(Duration::from_secs(5)));
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(requested_wait(&req), None);
+    }
+
+    #[test]
+    fn caps_wait() {
+        let req = TestRequest::with_uri("/api/v0/add?wait=600m").to_http_request();
+        assert_eq!(requested_wait(&req), Some(MAX_WAIT));
+
+        let req =
+            TestRequest::with_uri(&format!("/api/v0/add?wait={}m", u64::MAX)).to_http_request();
+        assert_eq!(requested_wait(&req), None);
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Satisfiable(0, 99)));
+        assert_eq!(
+            parse_range("bytes= 10 - 19 ", 1000),
+            Some(Satisfiable(10, 19))
+        );
+        assert_eq!(parse_range("bytes=0-0", 1), Some(Satisfiable(0, 0)));
+        // the end is clamped to the last byte
+        assert_eq!(
+            parse_range("bytes=900-5000", 1000),
+            Some(Satisfiable(900, 999))
+        );
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(parse_range("bytes=100-", 1000), Some(Satisfiable(100, 999)));
+        assert_eq!(parse_range("bytes=0-", 1000), Some(Satisfiable(0, 999)));
+        assert_eq!(parse_range("bytes=999-", 1000), Some(Satisfiable(999, 999)));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Satisfiable(900, 999)));
+        // a suffix longer than the content is the whole content
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Satisfiable(0, 999)));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Unsatisfiable));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=1000-1100", 1000), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=-1", 0), Some(Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_invalid_ranges() {
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("bytes=-", 1000), None);
+        assert_eq!(parse_range("bytes=10", 1000), None);
+        assert_eq!(parse_range("bytes=", 1000), None);
+        assert_eq!(parse_range("bytes=+10-20", 1000), None);
+        assert_eq!(parse_range("bytes=0-0x10", 1000), None);
+        assert_eq!(parse_range("items=0-10", 1000), None);
+        // the last byte comes before the first
+        assert_eq!(parse_range("bytes=20-10", 1000), None);
+        assert_eq!(parse_range("bytes=2000-1000", 1000), None);
+    }
+
+    #[test]
+    fn rejects_multiple_ranges() {
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), None);
+        assert_eq!(parse_range("bytes=0-10, -5", 1000), None);
+    }
 }
@@ -1,45 +1,1199 @@
 use std::{
+    collections::HashMap,
+    fmt::Debug,
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    sync::Arc,
 };
 
 use anyhow::*;
+use async_trait::async_trait;
+use futures::StreamExt;
+use regex::Regex;
+use reqwest::{header, RequestBuilder, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tempdir::TempDir;
+use tracing::{info, warn};
 
+use crate::{
+    config::{self, Secret},
+    daemon,
+    health::OriginalStatus,
+    network::{Component, Network},
+    snapshot::Snapshotter,
+    transcode::FormatPolicy,
+};
+
+/// The subdirectory of the download directory sidecar files are written to
+const SIDECAR_DIR: &str = "sidecars";
+
+/// Fetches the media of links, see `DownloadClient` for how links are routed to downloaders.
+#[async_trait(?Send)]
+pub trait Downloader: Debug + Send + Sync {
+    /// The name routes refer to the downloader by
+    fn name(&self) -> &str;
+
+    /// Downloads all media of the link into the empty directory.
+    async fn download(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Download>;
+
+    /// Checks whether the link is still online, see `LinkChecker`. `None` if the downloader has
+    /// no check of its own, the link is then checked with a plain request.
+    async fn check(&self, _url: &str, _options: &SiteOptions) -> Result<Option<OriginalStatus>> {
+        Ok(None)
+    }
+}
+
+/// Options for the links of a site
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SiteOptions {
+    /// A yt-dlp format selection, like `bestvideo[height<=720]+bestaudio/best`
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Extra command line arguments for yt-dlp or gallery-dl
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Downloads the whole playlist of links to a video in a playlist, like YouTube links with `&list=`,
+    /// instead of only the video. Links to a playlist itself are always downloaded whole.
+    #[serde(default)]
+    pub playlists: bool,
+    /// Replaces the global format policy for the site
+    #[serde(default)]
+    pub policy: Option<FormatPolicy>,
+    /// A proxy for the site's links, or `direct` to bypass the download proxy
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// A Netscape `cookies.txt` jar of a logged in session
+    #[serde(default)]
+    pub cookies: Option<PathBuf>,
+    /// The account yt-dlp or gallery-dl log in with
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Also sends the username and password as HTTP basic auth with plain requests, like file downloads,
+    /// snapshots and link checks. Only ever sent over https.
+    #[serde(default)]
+    pub basic_auth: bool,
+}
+
+impl SiteOptions {
+    /// Only the proxy, cookies and credentials, for a fallback downloader the other options aren't meant for
+    fn auth_only(&self) -> Self {
+        Self {
+            proxy: self.proxy.clone(),
+            cookies: self.cookies.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+            basic_auth: self.basic_auth,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the site's cookies for the URL to a request, and its credentials as basic auth if the site
+    /// opted into that and the URL is https.
+    pub(crate) fn authorize(&self, request: RequestBuilder, url: &Url) -> Result<RequestBuilder> {
+        let mut request = request;
+        if let Some(jar) = &self.cookies {
+            let jar = std::fs::read_to_string(jar).context("failed to read cookies file")?;
+            if let Some(cookies) = cookie_header(&jar, url) {
+                request = request.header(header::COOKIE, cookies);
+            }
+        }
+        match &self.username {
+            Some(username) if self.basic_auth && url.scheme() == "https" => {
+                request = request.basic_auth(username, self.password.as_ref().map(Secret::expose));
+            }
+            Some(_) if self.basic_auth => {
+                warn!(
+                    "not sending credentials over {}, only over https",
+                    url.scheme()
+                );
+            }
+            _ => {}
+        }
+        Ok(request)
+    }
+}
+
+/// The `Cookie` header value of the cookies in a Netscape cookie jar that apply to the URL
+fn cookie_header(jar: &str, url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let now = daemon::unix_time();
+    let cookies: Vec<String> = jar
+        .lines()
+        // http only cookies are marked by a prefix on what is otherwise a comment
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+                return None;
+            };
+            let domain = domain.trim_start_matches('.');
+            // cookies only apply to subdomains if the jar says so
+            let applies = (host == domain
+                || subdomains == "TRUE" && host.ends_with(&format!(".{domain}")))
+                && url.path().starts_with(path)
+                && (secure != "TRUE" || url.scheme() == "https")
+                // session cookies have no expiry
+                && expires.parse::<u64>().map_or(true, |e| e == 0 || e > now);
+            applies.then(|| format!("{name}={value}"))
+        })
+        .collect();
+    (!cookies.is_empty()).then(|| cookies.join("; "))
+}
+
+/// Writes a file only the daemon's user can read, for handing secrets to a downloader command
+/// without putting them on its command line.
+fn write_private(dir: &Path, name: &str, content: &[u8]) -> Result<PathBuf> {
+    let path = dir.join(name);
+    let mut options = std::fs::File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, content))
+        .context("failed to write private file")?;
+    Ok(path)
+}
+
+/// Copies the site's cookie jar to the private directory, as downloaders update the jar they're given.
+fn private_cookies(dir: &Path, options: &SiteOptions) -> Result<Option<PathBuf>> {
+    let Some(jar) = &options.cookies else {
+        return Ok(None);
+    };
+    let jar = std::fs::read(jar).context("failed to read cookies file")?;
+    write_private(dir, "cookies.txt", &jar).map(Some)
+}
+
+/// Quotes an option value for a yt-dlp config file, which is split like a shell command line.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r#"'"'"'"#))
+}
+
+/// Sends links to a downloader by their domain or a pattern
+#[derive(Debug, Clone, Deserialize)]
+pub struct Route {
+    /// Matches links to this domain and its subdomains
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Matches links matching this regular expression
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// The name of the downloader
+    pub downloader: String,
+    #[serde(flatten)]
+    pub options: SiteOptions,
+}
+
+impl Route {
+    fn matches(&self, url: &str, host: &str, pattern: Option<&Regex>) -> bool {
+        let domain = self.domain.as_deref().map(|domain| {
+            let domain = domain.trim_start_matches("www.");
+            host == domain || host.ends_with(&format!(".{domain}"))
+        });
+        let pattern = pattern.map(|pattern| pattern.is_match(url));
+        match (domain, pattern) {
+            (None, None) => false,
+            (domain, pattern) => domain.unwrap_or(true) && pattern.unwrap_or(true),
+        }
+    }
+}
+
+/// Which downloader each link goes to, as read from the downloaders file
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// Checked in order, the first matching route is used
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// The downloader of links no route matches
+    #[serde(default = "RouteConfig::default_downloader")]
+    pub default: String,
+    /// The downloader tried when the routed one fails, if any
+    #[serde(default)]
+    pub fallback: Option<String>,
+    /// Options for links no route matches
+    #[serde(default)]
+    pub options: SiteOptions,
+}
+
+impl RouteConfig {
+    fn default_downloader() -> String {
+        "yt-dlp".to_string()
+    }
+}
+
+/// Routes links to the downloader configured for their site, falling back to another downloader
+/// if that one fails. Clones are referenced counted.
 #[derive(Debug, Clone)]
-pub struct DownloadClient {}
+pub struct DownloadClient {
+    downloaders: Arc<HashMap<String, Arc<dyn Downloader>>>,
+    config: Arc<RouteConfig>,
+    /// The compiled pattern of each route
+    patterns: Arc<Vec<Option<Regex>>>,
+    /// The format policy of sites without their own
+    policy: Arc<FormatPolicy>,
+}
+
+/// A kind of file yt-dlp can fetch alongside the media
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sidecar {
+    /// Subtitles uploaded with the media, in all languages
+    Subtitles,
+    /// The thumbnail as the platform shows it
+    Thumbnail,
+    /// The metadata yt-dlp extracted, as JSON
+    Info,
+    /// Comments, written into the info JSON
+    Comments,
+}
+
+impl std::str::FromStr for Sidecar {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "subtitles" | "subs" => Ok(Sidecar::Subtitles),
+            "thumbnail" => Ok(Sidecar::Thumbnail),
+            "info" | "infojson" => Ok(Sidecar::Info),
+            "comments" => Ok(Sidecar::Comments),
+            _ => bail!("unknown sidecar {s}, expected subtitles, thumbnail, info or comments"),
+        }
+    }
+}
+
+impl Sidecar {
+    /// The kind of a downloaded sidecar file and its content type, from its file name
+    pub fn of_file(name: &str) -> (&'static str, &'static str) {
+        let extension = name.rsplit('.').next().unwrap_or_default();
+        match extension {
+            "json" => ("info", "application/json"),
+            "vtt" => ("subtitles", "text/vtt"),
+            "srt" => ("subtitles", "application/x-subrip"),
+            "ass" | "ssa" | "ttml" | "srv1" | "srv2" | "srv3" => ("subtitles", "text/plain"),
+            "jpg" | "jpeg" => ("thumbnail", "image/jpeg"),
+            "png" => ("thumbnail", "image/png"),
+            "webp" => ("thumbnail", "image/webp"),
+            _ => ("other", "application/octet-stream"),
+        }
+    }
+
+    /// The yt-dlp options that fetch this sidecar
+    fn options(self) -> &'static [&'static str] {
+        match self {
+            // live chat replays are huge and rarely useful
+            Sidecar::Subtitles => &["--write-subs", "--sub-langs", "all,-live_chat"],
+            Sidecar::Thumbnail => &["--write-thumbnail"],
+            Sidecar::Info => &["--write-info-json"],
+            Sidecar::Comments => &["--write-info-json", "--write-comments"],
+        }
+    }
+}
+
+/// A comma separated set of sidecars, `none` for none
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecars(pub Vec<Sidecar>);
+
+impl Default for Sidecars {
+    fn default() -> Self {
+        Self(vec![Sidecar::Subtitles, Sidecar::Thumbnail, Sidecar::Info])
+    }
+}
+
+impl std::str::FromStr for Sidecars {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty() && *s != "none")
+            .map(str::parse)
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+/// The files downloaded for a link
+#[derive(Debug, Default)]
+pub struct Download {
+    /// The media files, in the order they appear in the post, carousel or playlist
+    pub media: Vec<PathBuf>,
+    /// The directory holding the sidecar files, if any were downloaded
+    pub sidecars: Option<PathBuf>,
+    /// The readable text of a snapshotted page
+    pub text: Option<String>,
+    /// The files as downloaded, by the media file converted from them, if the format policy keeps them
+    pub originals: HashMap<PathBuf, PathBuf>,
+    /// The name of the downloader that fetched the files, set by `DownloadClient`
+    pub downloader: String,
+}
 
 impl DownloadClient {
-    pub fn new() -> Result<Self> {
-        Ok(Self {})
+    /// Sets up the yt-dlp, gallery-dl, http and snapshot downloaders, routed by the file at
+    /// `DOWNLOADERS_FILE` (default `downloaders.json`). Without the file, every link goes to yt-dlp,
+    /// falling back to a snapshot unless `SNAPSHOT_FALLBACK` is false.
+    ///
+    /// Reads `MAX_MEDIA_ITEMS` (default 100) and `SIDECARS` (default `subtitles,thumbnail,info`).
+    pub fn new(network: &Network) -> Result<Self> {
+        let max_items = config::var_or("MAX_MEDIA_ITEMS", 100)?;
+        let downloaders: Vec<Arc<dyn Downloader>> = vec![
+            Arc::new(YtDlp {
+                max_items,
+                sidecars: config::var_or("SIDECARS", Sidecars::default())?,
+                network: network.clone(),
+            }),
+            Arc::new(GalleryDl {
+                max_items,
+                network: network.clone(),
+            }),
+            Arc::new(HttpFile {
+                network: network.clone(),
+            }),
+            Arc::new(Snapshotter::new(network)?),
+        ];
+
+        let path = config::var_or("DOWNLOADERS_FILE", PathBuf::from("downloaders.json"))?;
+        let config = match path.exists() {
+            true => {
+                let file = std::fs::read(&path).with_context(|| {
+                    format!("failed to read downloaders file {}", path.display())
+                })?;
+                serde_json::from_slice(&file).context("failed to parse downloaders file")?
+            }
+            false => RouteConfig {
+                routes: vec![],
+                default: RouteConfig::default_downloader(),
+                fallback: config::var_or("SNAPSHOT_FALLBACK", true)?.then(|| "snapshot".into()),
+                options: SiteOptions::default(),
+            },
+        };
+
+        Self::with_downloaders(downloaders, config, FormatPolicy::from_env()?)
+    }
+
+    /// Routes links to the given downloaders. Fails if a route names a downloader that isn't given,
+    /// or has an invalid pattern or format policy.
+    pub fn with_downloaders(
+        downloaders: Vec<Arc<dyn Downloader>>,
+        config: RouteConfig,
+        policy: FormatPolicy,
+    ) -> Result<Self> {
+        let downloaders: HashMap<_, _> = downloaders
+            .into_iter()
+            .map(|d| (d.name().to_string(), d))
+            .collect();
+
+        let jars = config
+            .routes
+            .iter()
+            .map(|route| &route.options)
+            .chain([&config.options])
+            .filter_map(|options| options.cookies.as_ref());
+        for jar in jars {
+            ensure!(
+                jar.is_file(),
+                "cookies file {} doesn't exist",
+                jar.display()
+            );
+        }
+        let policies = config
+            .routes
+            .iter()
+            .map(|route| &route.options)
+            .chain([&config.options])
+            .filter_map(|options| options.policy.as_ref());
+        for policy in policies {
+            policy.validate().context("invalid route policy")?;
+        }
+        let names = config
+            .routes
+            .iter()
+            .map(|r| &r.downloader)
+            .chain([&config.default])
+            .chain(&config.fallback);
+        for name in names {
+            ensure!(downloaders.contains_key(name), "unknown downloader {name}");
+        }
+        let patterns = config
+            .routes
+            .iter()
+            .map(|route| route.pattern.as_deref().map(Regex::new).transpose())
+            .collect::<Result<_, _>>()
+            .context("invalid route pattern")?;
+
+        Ok(Self {
+            downloaders: Arc::new(downloaders),
+            config: Arc::new(config),
+            patterns: Arc::new(patterns),
+            policy: Arc::new(policy),
+        })
+    }
+
+    /// The downloader for the link and the options of its site
+    pub fn route(&self, url: &str) -> (&Arc<dyn Downloader>, &SiteOptions) {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let (name, options) = self
+            .config
+            .routes
+            .iter()
+            .zip(self.patterns.iter())
+            .find(|(route, pattern)| route.matches(url, &host, pattern.as_ref()))
+            .map_or(
+                (&self.config.default, &self.config.options),
+                |(route, _)| (&route.downloader, &route.options),
+            );
+        (&self.downloaders[name], options)
+    }
+
+    /// Downloads all media of the link into the empty directory with the downloader it's routed to.
+    /// If that fails, the fallback downloader gets a try, so links to articles can be archived as
+    /// snapshots of the page.
+    pub async fn download(&self, url: &str, dir: impl AsRef<Path>) -> Result<Download> {
+        let dir = dir.as_ref();
+        ensure_empty(dir)?;
+
+        let (downloader, options) = self.route(url);
+        let policy = options.policy.as_ref().unwrap_or(&self.policy);
+        let options = SiteOptions {
+            policy: Some(policy.clone()),
+            ..options.clone()
+        };
+        info!("downloading {url} with {}", downloader.name());
+        let download = self.fetch(url, dir, downloader, &options).await?;
+        Ok(convert(download, policy).await)
     }
 
-    pub async fn download(&self, url: &str, dir: impl AsRef<Path>) -> Result<PathBuf> {
+    /// Downloads all media of the link into the empty directory with the named downloader only, without
+    /// falling back, like when refreshing an entry with the downloader that archived it. The options of the
+    /// link's route apply if it routes to that downloader, else only its proxy and login.
+    pub async fn download_with(
+        &self,
+        url: &str,
+        dir: impl AsRef<Path>,
+        name: &str,
+    ) -> Result<Download> {
         let dir = dir.as_ref();
+        ensure_empty(dir)?;
+
+        let downloader = self
+            .downloaders
+            .get(name)
+            .with_context(|| format!("unknown downloader {name}"))?;
+        let (routed, options) = self.route(url);
+        let options = match routed.name() == name {
+            true => options.clone(),
+            false => options.auth_only(),
+        };
+        let policy = options.policy.as_ref().unwrap_or(&self.policy).clone();
+        let options = SiteOptions {
+            policy: Some(policy.clone()),
+            ..options
+        };
+        info!("downloading {url} with {name}");
+        let download = downloader.download(url, dir, &options).await?;
+        Ok(convert(
+            Download {
+                downloader: name.to_string(),
+                ..download
+            },
+            &policy,
+        )
+        .await)
+    }
 
-        ensure!(
-            std::fs::read_dir(dir)?.count() == 0,
-            "download client was passed an non-empty directory"
+    /// Downloads the link with the downloader, or the fallback downloader if that fails.
+    async fn fetch(
+        &self,
+        url: &str,
+        dir: &Path,
+        downloader: &Arc<dyn Downloader>,
+        options: &SiteOptions,
+    ) -> Result<Download> {
+        let result = downloader
+            .download(url, dir, options)
+            .await
+            .map(|download| Download {
+                downloader: downloader.name().to_string(),
+                ..download
+            });
+        let fallback = self
+            .config
+            .fallback
+            .as_ref()
+            .map(|name| &self.downloaders[name])
+            .filter(|fallback| fallback.name() != downloader.name());
+        let (Some(fallback), Err(error)) = (fallback, &result) else {
+            return result;
+        };
+        warn!(
+            "{} couldn't download {url}, trying {} instead: {error:#}",
+            downloader.name(),
+            fallback.name()
         );
 
-        let exit = Command::new("yt-dlp")
-            .args(["--add-header", "accept:*/*", "--no-playlist", url])
-            .stderr(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .current_dir(dir)
+        // whatever the first downloader left behind isn't part of the download
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            match path.is_dir() {
+                true => std::fs::remove_dir_all(path)?,
+                false => std::fs::remove_file(path)?,
+            }
+        }
+        let download = fallback
+            .download(url, dir, &options.auth_only())
+            .await
+            .map_err(|e| anyhow!("{error:#}, and {} failed too: {e:#}", fallback.name()))?;
+        Ok(Download {
+            downloader: fallback.name().to_string(),
+            ..download
+        })
+    }
+}
+
+fn ensure_empty(dir: &Path) -> Result<()> {
+    ensure!(
+        std::fs::read_dir(dir)?.count() == 0,
+        "download client was passed an non-empty directory"
+    );
+    Ok(())
+}
+
+/// Converts the downloaded media by the format policy. Files that fail to convert are kept as downloaded.
+async fn convert(mut download: Download, policy: &FormatPolicy) -> Download {
+    for file in &mut download.media {
+        let converted = policy.postprocess(file).await.unwrap_or_else(|e| {
+            warn!("couldn't convert {}: {e:#}", file.display());
+            None
+        });
+        if let Some(converted) = converted {
+            let original = std::mem::replace(file, converted);
+            if policy.keep_original {
+                download.originals.insert(file.clone(), original);
+            }
+        }
+    }
+    download
+}
+
+/// The files a downloader created in the directory, sorted by name
+fn downloaded_files(dir: &Path, downloader: &str) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        if file.file_type()?.is_file() {
+            files.push(file.path());
+        }
+    }
+    ensure!(!files.is_empty(), "{downloader} didn't create any files");
+    files.sort();
+    Ok(files)
+}
+
+/// Runs a downloader command in the directory, failing if it exits unsuccessfully. The command is
+/// killed if the download is cancelled.
+async fn run(mut command: Command, dir: &Path) -> Result<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    command
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .current_dir(dir);
+    let exit = tokio::process::Command::from(command)
+        .kill_on_drop(true)
+        .status()
+        .await
+        .with_context(|| format!("failed to run {program} command"))?;
+
+    ensure!(exit.success(), "{program} command failed with {exit}");
+    Ok(())
+}
+
+/// Downloads videos, audio and images from the many sites yt-dlp supports
+#[derive(Debug)]
+pub struct YtDlp {
+    /// The most files a single post or playlist may have
+    max_items: u32,
+    /// The sidecar files downloaded alongside the media
+    sidecars: Sidecars,
+    network: Network,
+}
+
+#[async_trait(?Send)]
+impl Downloader for YtDlp {
+    fn name(&self) -> &str {
+        "yt-dlp"
+    }
+
+    async fn download(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Download> {
+        // removed along with the secrets in it once the download is done
+        let secrets = TempDir::new("socialmediasecrets")?;
+        let mut command = self.command(secrets.path(), options)?;
+        for sidecar in &self.sidecars.0 {
+            command.args(sidecar.options());
+        }
+        // sidecars are kept apart from the media
+        for kind in [
+            "subtitle",
+            "thumbnail",
+            "infojson",
+            "pl_thumbnail",
+            "pl_infojson",
+        ] {
+            command.args(["--paths", &format!("{kind}:{SIDECAR_DIR}")]);
+        }
+        if let Some(policy) = &options.policy {
+            command.args(policy.yt_dlp_args());
+        }
+        // an explicit format selection overrides the policy's
+        if let Some(format) = &options.format {
+            command.args(["--format", format]);
+        }
+
+        command
+            .args(["--add-header", "accept:*/*"])
+            .arg(match options.playlists {
+                true => "--yes-playlist",
+                false => "--no-playlist",
+            })
+            .args(["--playlist-end", &self.max_items.to_string()])
+            // numbered so the files sort in the order they were downloaded
+            .args([
+                "--output",
+                "%(autonumber)04d %(title).100B [%(id)s].%(ext)s",
+            ])
+            .args(&options.args)
+            .arg(url);
+        run(command, dir).await?;
+
+        let sidecars = dir.join(SIDECAR_DIR);
+        let sidecars = match sidecars.is_dir() && std::fs::read_dir(&sidecars)?.count() > 0 {
+            true => Some(sidecars),
+            false => None,
+        };
+
+        Ok(Download {
+            media: downloaded_files(dir, self.name())?,
+            sidecars,
+            ..Default::default()
+        })
+    }
+
+    /// Simulates a download, since sites like YouTube answer links to deleted videos with a page of their own.
+    async fn check(&self, url: &str, options: &SiteOptions) -> Result<Option<OriginalStatus>> {
+        let secrets = TempDir::new("socialmediasecrets")?;
+        let mut command = self.command(secrets.path(), options)?;
+        command
+            .args([
+                "--simulate",
+                "--quiet",
+                "--no-warnings",
+                "--playlist-end",
+                "1",
+            ])
+            .arg(url)
+            .stdin(Stdio::null());
+        // run asynchronously so checks run concurrently, and killed if the check times out
+        let output = tokio::process::Command::from(command)
+            .kill_on_drop(true)
             .output()
-            .context("failed to run yt-dlp command")?
-            .status;
+            .await
+            .context("failed to run yt-dlp command")?;
+        if output.status.success() {
+            return Ok(Some(OriginalStatus::Available));
+        }
+
+        let error = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        match GONE_ERRORS.iter().any(|message| error.contains(message)) {
+            true => Ok(Some(OriginalStatus::Gone)),
+            false => bail!("yt-dlp command failed: {}", error.trim()),
+        }
+    }
+}
+
+/// Parts of the errors yt-dlp reports for deleted, removed or missing posts
+const GONE_ERRORS: &[&str] = &[
+    "http error 404",
+    "http error 410",
+    "video unavailable",
+    "this video has been removed",
+    "this video is no longer available",
+    "has been deleted",
+    "does not exist",
+    "account has been terminated",
+    "account suspended",
+    "post is unavailable",
+    "content isn't available",
+];
+
+impl YtDlp {
+    /// A yt-dlp command that connects and logs in as configured for the site. Cookies and credentials are
+    /// written to the secrets directory, which should be removed once the command is done.
+    fn command(&self, secrets: &Path, options: &SiteOptions) -> Result<Command> {
+        let mut command = Command::new("yt-dlp");
+        self.network
+            .configure_command(&mut command, options.proxy.as_deref());
+        if let Some(jar) = private_cookies(secrets, options)? {
+            command.arg("--cookies").arg(jar);
+        }
+        if let Some(username) = &options.username {
+            let password = options.password.as_ref().map_or("", Secret::expose);
+            let config = format!(
+                "--username {}\n--password {}\n",
+                shell_quote(username),
+                shell_quote(password)
+            );
+            let config = write_private(secrets, "yt-dlp.conf", config.as_bytes())?;
+            command.arg("--config-locations").arg(config);
+        }
+        Ok(command)
+    }
+}
+
+/// Downloads image galleries, like Instagram carousels, that yt-dlp doesn't handle well
+#[derive(Debug)]
+pub struct GalleryDl {
+    /// The most files a single gallery may have
+    max_items: u32,
+    network: Network,
+}
+
+#[async_trait(?Send)]
+impl Downloader for GalleryDl {
+    fn name(&self) -> &str {
+        "gallery-dl"
+    }
+
+    async fn download(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Download> {
+        let mut command = Command::new("gallery-dl");
+        self.network
+            .configure_command(&mut command, options.proxy.as_deref());
+
+        // removed along with the secrets in it once the download is done
+        let secrets = TempDir::new("socialmediasecrets")?;
+        if let Some(jar) = private_cookies(secrets.path(), options)? {
+            command.arg("--cookies").arg(jar);
+        }
+        if let Some(username) = &options.username {
+            let config = json!({
+                "extractor": {
+                    "username": username,
+                    "password": options.password.as_ref().map(Secret::expose),
+                }
+            });
+            let config = write_private(
+                secrets.path(),
+                "gallery-dl.json",
+                config.to_string().as_bytes(),
+            )?;
+            command.arg("--config").arg(config);
+        }
+
+        command
+            .arg("--directory")
+            .arg(dir)
+            .args(["--range", &format!("1-{}", self.max_items)])
+            .args(&options.args)
+            .arg(url);
+        run(command, dir).await?;
+
+        // gallery-dl names files by the post and their number in it
+        Ok(Download {
+            media: downloaded_files(dir, self.name())?,
+            ..Default::default()
+        })
+    }
+}
+
+/// Fetches the link as is, for direct links to files
+#[derive(Debug)]
+pub struct HttpFile {
+    network: Network,
+}
+
+#[async_trait(?Send)]
+impl Downloader for HttpFile {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn download(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Download> {
+        let url = Url::parse(url).context("invalid link")?;
+        let client = self
+            .network
+            .client(Component::Download, options.proxy.as_deref())?;
+        let resp = options
+            .authorize(client.get(url.clone()), &url)?
+            .send()
+            .await
+            .context("failed to fetch file")?
+            .error_for_status()
+            .context("file link returned an error")?;
+
+        let name = resp
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|name| name.replace(|c: char| !c.is_alphanumeric() && c != '.', "_"))
+            .filter(|name| !name.trim_matches(['.', '_']).is_empty())
+            .unwrap_or_else(|| "download".to_string());
+        let file = dir.join(name);
+
+        let mut out = std::fs::File::create(&file).context("failed to create download file")?;
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            std::io::Write::write_all(&mut out, &chunk.context("failed to fetch file")?)?;
+        }
+
+        Ok(Download {
+            media: vec![file],
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Downloader for Snapshotter {
+    fn name(&self) -> &str {
+        "snapshot"
+    }
 
-        ensure!(exit.success(), "yt-dlp command failed with {exit}");
+    async fn download(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Download> {
+        let snapshot = self.capture(url, dir, options).await?;
+        Ok(Download {
+            media: vec![snapshot.file],
+            text: Some(snapshot.text),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeDownloader;
+
+    fn fake(name: &'static str) -> Arc<FakeDownloader> {
+        Arc::new(FakeDownloader {
+            name,
+            files: vec![("file.txt", name)],
+            ..Default::default()
+        })
+    }
+
+    fn route(domain: Option<&str>, pattern: Option<&str>, downloader: &str) -> Route {
+        Route {
+            domain: domain.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+            downloader: downloader.to_string(),
+            options: SiteOptions::default(),
+        }
+    }
+
+    fn config(routes: Vec<Route>, fallback: Option<&str>) -> RouteConfig {
+        RouteConfig {
+            routes,
+            default: "default".to_string(),
+            fallback: fallback.map(str::to_string),
+            options: SiteOptions::default(),
+        }
+    }
+
+    fn client(names: &[&'static str], config: RouteConfig) -> Result<DownloadClient> {
+        let downloaders = names
+            .iter()
+            .map(|name| fake(name) as Arc<dyn Downloader>)
+            .collect();
+        DownloadClient::with_downloaders(downloaders, config, FormatPolicy::default())
+    }
 
-        let file_count = std::fs::read_dir(dir)?.count();
-        ensure!(
-            file_count == 1,
-            "yt-dlp created {file_count} files instead of one"
+    fn routed(client: &DownloadClient, url: &str) -> String {
+        client.route(url).0.name().to_string()
+    }
+
+    #[test]
+    fn routes_by_domain_and_subdomain() {
+        let client = client(
+            &["default", "video", "gallery"],
+            config(
+                vec![
+                    route(Some("youtube.com"), None, "video"),
+                    route(Some("www.instagram.com"), None, "gallery"),
+                ],
+                None,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(routed(&client, "https://youtube.com/watch?v=1"), "video");
+        assert_eq!(
+            routed(&client, "https://www.youtube.com/watch?v=1"),
+            "video"
+        );
+        assert_eq!(routed(&client, "https://m.youtube.com/watch?v=1"), "video");
+        assert_eq!(routed(&client, "https://instagram.com/p/1"), "gallery");
+        assert_eq!(routed(&client, "https://notyoutube.com/watch"), "default");
+        assert_eq!(
+            routed(&client, "https://youtube.com.example/watch"),
+            "default"
+        );
+        assert_eq!(routed(&client, "not a link"), "default");
+    }
+
+    #[test]
+    fn routes_by_pattern() {
+        let client = client(
+            &["default", "http", "video"],
+            config(
+                vec![
+                    route(Some("example.com"), Some("/videos/"), "video"),
+                    route(None, Some(r"\.(pdf|zip)$"), "http"),
+                ],
+                None,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(routed(&client, "https://files.org/a.pdf"), "http");
+        assert_eq!(routed(&client, "https://files.org/a.pdf?x=1"), "default");
+        // both the domain and the pattern have to match
+        assert_eq!(routed(&client, "https://example.com/videos/1"), "video");
+        assert_eq!(routed(&client, "https://example.com/posts/1"), "default");
+        assert_eq!(routed(&client, "https://other.com/videos/1"), "default");
+    }
+
+    #[test]
+    fn uses_the_first_matching_route() {
+        let client = client(
+            &["default", "first", "second"],
+            config(
+                vec![
+                    route(Some("example.com"), None, "first"),
+                    route(None, Some("example"), "second"),
+                    // matches nothing without a domain or pattern
+                    route(None, None, "second"),
+                ],
+                None,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(routed(&client, "https://example.com/"), "first");
+        assert_eq!(routed(&client, "https://example.org/"), "second");
+        assert_eq!(routed(&client, "https://other.org/"), "default");
+    }
+
+    #[test]
+    fn rejects_unknown_downloaders() {
+        let routes = vec![route(Some("example.com"), None, "missing")];
+        let error = client(&["default"], config(routes, None)).unwrap_err();
+        assert!(error.to_string().contains("unknown downloader missing"));
+
+        assert!(client(&["default"], config(vec![], Some("missing"))).is_err());
+        assert!(client(&["other"], config(vec![], None)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let routes = vec![route(None, Some("(unclosed"), "default")];
+        let error = client(&["default"], config(routes, None)).unwrap_err();
+        assert!(error.to_string().contains("invalid route pattern"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_with_a_clean_directory_and_only_the_login() {
+        let primary = Arc::new(FakeDownloader {
+            name: "primary",
+            files: vec![("partial.part", "broken"), ("other.txt", "broken")],
+            fail: true,
+            ..Default::default()
+        });
+        let fallback = fake("fallback");
+        let mut config = config(
+            vec![Route {
+                options: SiteOptions {
+                    format: Some("best".to_string()),
+                    args: vec!["--verbose".to_string()],
+                    playlists: true,
+                    proxy: Some("http://proxy:3128".to_string()),
+                    username: Some("archive".to_string()),
+                    password: Some(Secret::new("hunter2")),
+                    ..Default::default()
+                },
+                ..route(Some("example.com"), None, "primary")
+            }],
+            Some("fallback"),
         );
+        config.default = "primary".to_string();
+        let client = DownloadClient::with_downloaders(
+            vec![primary.clone(), fallback.clone()],
+            config,
+            FormatPolicy::default(),
+        )
+        .unwrap();
+
+        let dir = TempDir::new("download").unwrap();
+        let download = client
+            .download("https://example.com/post", dir.path())
+            .await
+            .unwrap();
+        assert_eq!(download.media, [dir.path().join("file.txt")]);
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|f| f.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["file.txt"]);
+
+        let primary_options = &primary.options.lock().unwrap()[0];
+        assert_eq!(primary_options.format.as_deref(), Some("best"));
+        assert!(primary_options.policy.is_some());
 
-        let outfile = std::fs::read_dir(dir)?.next().unwrap()?.path();
+        let options = &fallback.options.lock().unwrap()[0];
+        assert_eq!(options.format, None);
+        assert!(options.args.is_empty());
+        assert!(!options.playlists);
+        assert!(options.policy.is_none());
+        assert_eq!(options.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(options.username.as_deref(), Some("archive"));
+        assert_eq!(
+            options.password.as_ref().map(Secret::expose),
+            Some("hunter2")
+        );
+    }
 
-        Ok(outfile)
+    #[tokio::test]
+    async fn reports_both_errors_when_the_fallback_fails() {
+        let failing = |name| {
+            Arc::new(FakeDownloader {
+                name,
+                fail: true,
+                ..Default::default()
+            }) as Arc<dyn Downloader>
+        };
+        let client = DownloadClient::with_downloaders(
+            vec![failing("default"), failing("fallback")],
+            config(vec![], Some("fallback")),
+            FormatPolicy::default(),
+        )
+        .unwrap();
+
+        let dir = TempDir::new("download").unwrap();
+        let error = client
+            .download("https://example.com/post", dir.path())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("default failed"), "{error}");
+        assert!(error.contains("fallback failed too"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn refuses_non_empty_directories() {
+        let client = client(&["default"], config(vec![], None)).unwrap();
+        let dir = TempDir::new("download").unwrap();
+        std::fs::write(dir.path().join("leftover"), "").unwrap();
+        assert!(client
+            .download("https://example.com/post", dir.path())
+            .await
+            .is_err());
+    }
+
+    fn authorization(options: &SiteOptions, url: &str) -> Option<String> {
+        let url = Url::parse(url).unwrap();
+        let request = options
+            .authorize(reqwest::Client::new().get(url.clone()), &url)
+            .unwrap()
+            .build()
+            .unwrap();
+        request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn sends_basic_auth_only_when_opted_in_over_https() {
+        let login = SiteOptions {
+            username: Some("archive".to_string()),
+            password: Some(Secret::new("hunter2")),
+            ..Default::default()
+        };
+        assert_eq!(authorization(&login, "https://example.com/"), None);
+
+        let basic_auth = SiteOptions {
+            basic_auth: true,
+            ..login
+        };
+        assert_eq!(
+            authorization(&basic_auth, "https://example.com/").as_deref(),
+            Some("Basic YXJjaGl2ZTpodW50ZXIy")
+        );
+        assert_eq!(authorization(&basic_auth, "http://example.com/"), None);
+        assert!(basic_auth.auth_only().basic_auth);
+    }
+
+    #[test]
+    fn rejects_invalid_route_policies() {
+        let policy = FormatPolicy {
+            container: "mkv".to_string(),
+            ..Default::default()
+        };
+        let routes = vec![Route {
+            options: SiteOptions {
+                policy: Some(policy.clone()),
+                ..Default::default()
+            },
+            ..route(Some("example.com"), None, "default")
+        }];
+        let error = client(&["default"], config(routes, None)).unwrap_err();
+        assert!(format!("{error:#}").contains("unsupported container mkv"));
+
+        let mut unrouted = config(vec![], None);
+        unrouted.options.policy = Some(policy);
+        assert!(client(&["default"], unrouted).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_cookie_files() {
+        let mut routes = vec![route(Some("example.com"), None, "default")];
+        routes[0].options.cookies = Some(PathBuf::from("/nonexistent/cookies.txt"));
+        assert!(client(&["default"], config(routes, None)).is_err());
+
+        let mut unrouted = config(vec![], None);
+        unrouted.options.cookies = Some(PathBuf::from("/nonexistent/cookies.txt"));
+        let error = client(&["default"], unrouted).unwrap_err();
+        assert!(error.to_string().contains("doesn't exist"));
+    }
+
+    #[test]
+    fn sends_cookies_for_their_domain() {
+        let jar = [
+            "# Netscape HTTP Cookie File",
+            "example.com\tFALSE\t/\tFALSE\t0\texact\t1",
+            ".example.com\tTRUE\t/\tFALSE\t0\tshared\t2",
+            "#HttpOnly_example.com\tFALSE\t/\tTRUE\t0\tsecure\t3",
+            "example.com\tFALSE\t/private\tFALSE\t0\tprivate\t4",
+            "example.com\tFALSE\t/\tFALSE\t1\texpired\t5",
+        ]
+        .join("\n");
+        let cookies = |url: &str| cookie_header(&jar, &Url::parse(url).unwrap());
+
+        assert_eq!(
+            cookies("https://example.com/post").as_deref(),
+            Some("exact=1; shared=2; secure=3")
+        );
+        assert_eq!(
+            cookies("http://example.com/private/post").as_deref(),
+            Some("exact=1; shared=2; private=4")
+        );
+        // only cookies that include subdomains apply to them
+        assert_eq!(
+            cookies("https://www.example.com/post").as_deref(),
+            Some("shared=2")
+        );
+        assert_eq!(cookies("https://notexample.com/post"), None);
     }
 }
@@ -1,31 +1,133 @@
-use anyhow::{ensure, Context, Result};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use serde_json::{from_value, json, Value};
-use tracing::instrument;
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{instrument, warn};
+
+use crate::{
+    config,
+    network::{Component, Network},
+};
+
+/// The OpenAI model used for all embeddings
+const MODEL: &str = "text-embedding-ada-002";
+
+/// The most inputs OpenAi accepts in one embeddings request
+const MAX_BATCH_INPUTS: usize = 2048;
+
+/// The most estimated tokens sent in one embeddings request
+const MAX_BATCH_TOKENS: usize = 100_000;
+
+/// An embedding vector along with the API tokens consumed to generate it
+#[derive(Debug, Clone)]
+pub struct Embedding {
+    pub vector: Vec<f32>,
+    pub tokens: u64,
+}
 
+/// An input waiting to be embedded in the next batch, along with where to send its result
+type BatchRequest = (String, oneshot::Sender<Result<Embedding>>);
+
+/// Clones are referenced counted.
 #[derive(Debug, Clone)]
 pub struct EmbeddingClient {
     key: String,
     client: Client,
+    cache: Option<EmbeddingCache>,
+    batcher: mpsc::UnboundedSender<BatchRequest>,
 }
 
 impl EmbeddingClient {
-    pub fn new() -> Result<Self> {
-        Ok(Self {
+    /// Creates the client and spawns the task that coalesces concurrent `generate` calls into batches,
+    /// so it must be called from within a tokio runtime.
+    pub fn new(network: &Network) -> Result<Self> {
+        let (batcher, requests) = mpsc::unbounded_channel();
+        let client = Self {
             key: std::env::var("OPENAI_KEY").context("OPENAI_KEY env variable not set")?,
-            client: Client::new(),
-        })
+            client: network.client(Component::OpenAi, None)?,
+            cache: EmbeddingCache::from_env().context("failed to open embedding cache")?,
+            batcher,
+        };
+
+        tokio::spawn(client.clone().coalesce_batches(requests));
+        Ok(client)
+    }
+
+    /// Whether the embedding of the input is cached, so generating it won't consume tokens.
+    pub fn is_cached(&self, input: &str) -> bool {
+        self.cache
+            .as_ref()
+            .map(|cache| cache.contains(input))
+            .unwrap_or(false)
     }
 
+    /// The hit and miss counts of the embedding cache, `None` if it's disabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(EmbeddingCache::stats)
+    }
+
+    /// Generates the embedding of the input, served from the cache if possible.
+    ///
+    /// Inputs of concurrent calls are sent to OpenAi together in one batch.
     #[instrument(skip_all)]
-    pub async fn generate(&self, input: &str) -> Result<Vec<f32>> {
+    pub async fn generate(&self, input: &str) -> Result<Embedding> {
+        let (sender, receiver) = oneshot::channel();
+        self.batcher
+            .send((input.to_string(), sender))
+            .map_err(|_| anyhow!("embedding batcher stopped"))?;
+        receiver.await.context("embedding batcher stopped")?
+    }
+
+    /// Generates the embeddings of the inputs in order, served from the cache if possible.
+    /// The inputs that aren't cached are sent to OpenAi in as few requests as possible.
+    #[instrument(skip_all, fields(inputs = inputs.len()))]
+    pub async fn generate_batch(&self, inputs: &[&str]) -> Result<Vec<Embedding>> {
+        let mut embeddings: Vec<_> = inputs
+            .iter()
+            .map(|input| {
+                let vector = self.cache.as_ref()?.get(input)?;
+                Some(Embedding { vector, tokens: 0 })
+            })
+            .collect();
+
+        let missing: Vec<_> = (0..inputs.len())
+            .filter(|&i| embeddings[i].is_none())
+            .collect();
+        for batch in split_batches(&missing, |&i| estimate_tokens(inputs[i])) {
+            let batch_inputs: Vec<_> = batch.iter().map(|&i| inputs[i]).collect();
+            let generated = self.request_batch(&batch_inputs).await?;
+
+            for (&i, embedding) in batch.iter().zip(generated) {
+                if let Some(cache) = &self.cache {
+                    cache.insert(inputs[i], &embedding.vector);
+                }
+                embeddings[i] = Some(embedding);
+            }
+        }
+
+        Ok(embeddings.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Sends one embeddings request for all inputs, returns the embeddings in order.
+    /// The tokens OpenAi reports for the request are split among the inputs by their estimated size.
+    async fn request_batch(&self, inputs: &[&str]) -> Result<Vec<Embedding>> {
         let mut resp: Value = self
             .client
             .post("https://api.openai.com/v1/embeddings")
             .bearer_auth(&self.key)
             .json(&json!({
-                "model": "text-embedding-ada-002",
-                "input": input
+                "model": MODEL,
+                "input": inputs
             }))
             .send()
             .await
@@ -39,6 +141,268 @@ impl EmbeddingClient {
             resp["error"]
         );
 
-        Ok(from_value(resp["data"][0]["embedding"].take())?)
+        let mut data: Vec<Value> = from_value(resp["data"].take())?;
+        ensure!(
+            data.len() == inputs.len(),
+            "OpenAi returned {} embeddings for {} inputs",
+            data.len(),
+            inputs.len()
+        );
+        data.sort_by_key(|d| d["index"].as_u64());
+
+        let total_tokens = resp["usage"]["total_tokens"].as_u64().unwrap_or_default();
+        let estimated: Vec<_> = inputs.iter().map(|i| estimate_tokens(i) as u64).collect();
+        let estimated_total: u64 = estimated.iter().sum();
+        let mut remaining_tokens = total_tokens;
+
+        data.into_iter()
+            .zip(estimated)
+            .enumerate()
+            .map(|(i, (mut d, estimate))| {
+                let tokens = match i + 1 == inputs.len() {
+                    true => remaining_tokens,
+                    false => {
+                        (total_tokens * estimate / estimated_total.max(1)).min(remaining_tokens)
+                    }
+                };
+                remaining_tokens -= tokens;
+
+                Ok(Embedding {
+                    vector: from_value(d["embedding"].take())?,
+                    tokens,
+                })
+            })
+            .collect()
+    }
+
+    /// Answers `generate` calls in batches. Takes every request queued up while the previous batch
+    /// was in flight, so lone calls aren't delayed and concurrent calls share a request.
+    async fn coalesce_batches(self, mut requests: mpsc::UnboundedReceiver<BatchRequest>) {
+        while let Some(first) = requests.recv().await {
+            let mut batch = vec![first];
+            while batch.len() < MAX_BATCH_INPUTS {
+                match requests.try_recv() {
+                    Ok(request) => batch.push(request),
+                    Err(_) => break,
+                }
+            }
+
+            let (inputs, senders): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            let inputs: Vec<_> = inputs.iter().map(String::as_str).collect();
+            match self.generate_batch(&inputs).await {
+                Ok(embeddings) => {
+                    for (sender, embedding) in senders.into_iter().zip(embeddings) {
+                        let _ = sender.send(Ok(embedding));
+                    }
+                }
+                // one bad input or a failed request mustn't fail the unrelated calls batched with it,
+                // so the inputs are retried one by one and each call gets its own result
+                Err(e) if inputs.len() > 1 => {
+                    warn!(
+                        "embeddings batch of {} inputs failed, retrying them one by one: {e:#}",
+                        inputs.len()
+                    );
+                    for (sender, input) in senders.into_iter().zip(inputs) {
+                        let result = self.generate_batch(&[input]).await;
+                        let _ = sender.send(result.map(|mut embeddings| embeddings.remove(0)));
+                    }
+                }
+                Err(e) => {
+                    if let Some(sender) = senders.into_iter().next() {
+                        let _ = sender.send(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Roughly estimates the tokens of an input, erring on the high side for english text.
+pub(crate) fn estimate_tokens(input: &str) -> usize {
+    input.len() / 3 + 1
+}
+
+/// Splits the items into batches within the input and estimated token limits of a request.
+fn split_batches<T>(items: &[T], tokens: impl Fn(&T) -> usize) -> Vec<&[T]> {
+    let mut batches = vec![];
+    let (mut start, mut batch_tokens) = (0, 0);
+    for (i, item) in items.iter().enumerate() {
+        let item_tokens = tokens(item);
+        if i > start
+            && (i - start >= MAX_BATCH_INPUTS || batch_tokens + item_tokens > MAX_BATCH_TOKENS)
+        {
+            batches.push(&items[start..i]);
+            (start, batch_tokens) = (i, 0);
+        }
+        batch_tokens += item_tokens;
+    }
+    if start < items.len() {
+        batches.push(&items[start..]);
+    }
+    batches
+}
+
+/// The hit and miss counts of the embedding cache since the process started
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// A persistent cache of embeddings, keyed by the hash of the model and the trimmed input.
+/// Evicts the least recently used embeddings past its size limit.
+///
+/// Cache hits never contact OpenAi, so cached queries keep working while it's down.
+/// Failures of the cache itself are logged and treated as misses.
+/// Clones are referenced counted.
+#[derive(Debug, Clone)]
+struct EmbeddingCache {
+    /// Maps keys to the last use sequence number followed by the vector
+    entries: sled::Tree,
+    /// Maps last use sequence numbers to keys, the oldest use comes first
+    recency: sled::Tree,
+    db: sled::Db,
+    /// The number of entries, counted separately since counting a sled tree is linear
+    len: Arc<AtomicUsize>,
+    max_entries: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+}
+
+impl EmbeddingCache {
+    /// Opens the cache at `EMBEDDING_CACHE_DIR` (default `embedding_cache`) holding up to
+    /// `EMBEDDING_CACHE_MAX_ENTRIES` (default 100000) embeddings, `None` if the limit is 0.
+    ///
+    /// The cache can only be opened by one process at a time, so commands run next to the daemon
+    /// go without a cache rather than failing.
+    fn from_env() -> Result<Option<Self>> {
+        let max_entries = config::var_or("EMBEDDING_CACHE_MAX_ENTRIES", 100_000)?;
+        if max_entries == 0 {
+            return Ok(None);
+        }
+
+        let dir = config::var_or("EMBEDDING_CACHE_DIR", PathBuf::from("embedding_cache"))?;
+        let db = match sled::open(&dir) {
+            Err(sled::Error::Io(e)) if e.to_string().starts_with("could not acquire lock") => {
+                warn!(
+                    "embedding cache {} is in use by another process, running without it",
+                    dir.display()
+                );
+                return Ok(None);
+            }
+            db => db?,
+        };
+        let entries = db.open_tree("entries")?;
+        Ok(Some(Self {
+            len: Arc::new(AtomicUsize::new(entries.len())),
+            entries,
+            recency: db.open_tree("recency")?,
+            db,
+            max_entries,
+            hits: Default::default(),
+            misses: Default::default(),
+            evictions: Default::default(),
+        }))
+    }
+
+    fn key(input: &str) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(MODEL);
+        hasher.update([0]);
+        hasher.update(input.trim());
+        hasher.finalize().to_vec()
+    }
+
+    fn contains(&self, input: &str) -> bool {
+        self.entries.contains_key(Self::key(input)).unwrap_or(false)
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.len.load(Ordering::Relaxed),
+            max_entries: self.max_entries,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get(&self, input: &str) -> Option<Vec<f32>> {
+        match self.try_get(input) {
+            Ok(Some(vector)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(vector)
+            }
+            Ok(None) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(e) => {
+                warn!("failed to read embedding cache: {e:#}");
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn try_get(&self, input: &str) -> Result<Option<Vec<f32>>> {
+        let key = Self::key(input);
+        let Some(value) = self.entries.get(&key)? else {
+            return Ok(None);
+        };
+
+        // mark the entry as the most recently used
+        let vector = value[8..].to_vec();
+        self.touch(&key, &vector)?;
+
+        Ok(Some(
+            vector
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    fn insert(&self, input: &str, vector: &[f32]) {
+        let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+        if let Err(e) = self
+            .touch(&Self::key(input), &bytes)
+            .and_then(|_| self.evict())
+        {
+            warn!("failed to write embedding cache: {e:#}");
+        }
+    }
+
+    /// Stores the entry with a new last use sequence number.
+    fn touch(&self, key: &[u8], vector: &[u8]) -> Result<()> {
+        let last_use = self.db.generate_id()?.to_be_bytes();
+        self.recency.insert(last_use, key)?;
+        match self.entries.insert(key, [&last_use[..], vector].concat())? {
+            Some(old) => {
+                self.recency.remove(&old[..8])?;
+            }
+            None => {
+                self.len.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache is within its size limit.
+    fn evict(&self) -> Result<()> {
+        while self.len.load(Ordering::Relaxed) > self.max_entries {
+            let Some((_, key)) = self.recency.pop_min()? else {
+                break;
+            };
+            if self.entries.remove(key)?.is_some() {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{api::ClientApi, config, storage, LocalClient};
+
+/// The longest re-pinning a file may take, pins of content the IPFS network doesn't have never finish
+const PIN_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long `--gc` waits for an answer of the daemon
+const DAEMON_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What `fsck` checks beyond pins, and what it repairs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsckOptions {
+    /// Reads every referenced file and checks that it hashes to its CID
+    pub verify: bool,
+    /// Pins dangling files again, fetching them from the IPFS network if needed
+    pub repin: bool,
+    /// Downloads the links of entries with files that are still missing or damaged again
+    pub redownload: bool,
+    /// Unpins orphaned pins, so the IPFS node's garbage collection frees them. Refuses to run while the daemon
+    /// is up, since adds in progress pin their files before their entries exist
+    pub gc: bool,
+}
+
+/// A file or entry that couldn't be checked or repaired
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FsckFailure {
+    /// The CID or entry ID
+    pub id: String,
+    pub error: String,
+}
+
+/// What `fsck` found and repaired
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FsckReport {
+    pub entries: usize,
+    /// How many distinct files the entries reference
+    pub files: usize,
+    /// Referenced files that aren't pinned, so the IPFS node may lose them, with the IDs of the entries referencing them
+    pub dangling: BTreeMap<String, Vec<String>>,
+    /// Referenced files that can't be read or don't hash to their CID, with the IDs of the entries referencing them
+    pub corrupt: BTreeMap<String, Vec<String>>,
+    /// Pins no entry references
+    pub orphaned: Vec<String>,
+    pub repinned: Vec<String>,
+    /// The IDs of the entries downloaded again whose broken files were restored
+    pub redownloaded: Vec<String>,
+    pub unpinned: Vec<String>,
+    pub failed: Vec<FsckFailure>,
+}
+
+impl FsckReport {
+    /// Whether nothing was found wrong with the archive
+    pub fn is_clean(&self) -> bool {
+        self.dangling.is_empty()
+            && self.corrupt.is_empty()
+            && self.orphaned.is_empty()
+            && self.failed.is_empty()
+    }
+}
+
+/// Cross-checks the entries in the vector database against the files pinned in storage: every file an entry
+/// references should be pinned, and every pin should be referenced by an entry. Repairs what the options ask for.
+///
+/// Old versions of an entry's content are checked too, but only its current content can be downloaded again.
+/// Verifying hashes assumes files were added with the IPFS defaults, as the storage client adds them.
+pub async fn fsck(client: &LocalClient, options: FsckOptions) -> Result<FsckReport> {
+    if options.gc {
+        let url = config::var_or("API_URL", "http://localhost:5003".to_string())?;
+        if daemon_running(&url).await? {
+            bail!("the daemon at {url} is running, stop it before collecting garbage");
+        }
+    }
+    let mut report = FsckReport::default();
+
+    let pins = client.storage.pins().await?;
+    // the entries referencing each file, and the files pinned on their own rather than within a directory
+    let mut references: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut pinned_by_entries = HashSet::new();
+    // the files of each entry's current content, the only ones downloading it again can restore
+    let mut current: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for stored in client.vector.all(false).await? {
+        let entry = stored.entry;
+        for cid in storage::referenced_cids(&entry.payload) {
+            references.entry(cid).or_default().push(entry.id.clone());
+        }
+        current.insert(entry.id.clone(), current_cids(&entry.payload));
+        pinned_by_entries.extend(storage::pinned_cids(&entry.payload, &pins));
+        report.entries += 1;
+    }
+    report.files = references.len();
+
+    for (cid, ids) in &references {
+        let pinned = match pins.contains(cid) {
+            true => true,
+            false => match client.storage.is_pinned(cid).await {
+                Ok(pinned) => pinned,
+                Err(e) => {
+                    report.failed.push(failure(cid, e));
+                    continue;
+                }
+            },
+        };
+        if !pinned {
+            report.dangling.insert(cid.clone(), ids.clone());
+        } else if options.verify {
+            match client.storage.rehash(cid).await {
+                Ok(hash) if hash.0 == *cid => {}
+                Ok(hash) => {
+                    warn!("{cid} hashes to {hash}");
+                    report.corrupt.insert(cid.clone(), ids.clone());
+                }
+                Err(e) => {
+                    warn!("couldn't read {cid}: {e:#}");
+                    report.corrupt.insert(cid.clone(), ids.clone());
+                }
+            }
+        }
+    }
+    report.orphaned = pins
+        .into_iter()
+        .filter(|cid| !pinned_by_entries.contains(cid))
+        .collect();
+    report.orphaned.sort();
+
+    if options.repin {
+        for cid in report.dangling.keys() {
+            match tokio::time::timeout(PIN_TIMEOUT, client.storage.pin(cid)).await {
+                Ok(Ok(())) => report.repinned.push(cid.clone()),
+                Ok(Err(e)) => report.failed.push(failure(cid, e)),
+                Err(_) => report
+                    .failed
+                    .push(failure(cid, anyhow!("content wasn't found in time"))),
+            }
+        }
+    }
+
+    if options.redownload {
+        // the entries with broken current content, and their broken files
+        let mut broken: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+        let dangling = report
+            .dangling
+            .iter()
+            .filter(|(cid, _)| !report.repinned.contains(cid));
+        for (cid, ids) in dangling.chain(&report.corrupt) {
+            for id in ids.iter().filter(|id| current[*id].contains(cid)) {
+                broken.entry(id).or_default().push(cid);
+            }
+        }
+        for (id, cids) in broken {
+            info!("downloading entry {id} again");
+            if let Err(e) = client.refresh_entry(id).await {
+                report.failed.push(failure(id, e));
+                continue;
+            }
+            // the download only restores the files if the content didn't change since it was archived
+            let mut missing = vec![];
+            for cid in cids {
+                let restored = match report.corrupt.contains_key(cid) {
+                    true => client.storage.rehash(cid).await.map(|hash| hash.0 == *cid),
+                    false => client.storage.is_pinned(cid).await,
+                };
+                if !restored.unwrap_or(false) {
+                    missing.push(cid.as_str());
+                }
+            }
+            match missing.is_empty() {
+                true => report.redownloaded.push(id.clone()),
+                false => report.failed.push(failure(
+                    id,
+                    anyhow!(
+                        "downloaded again, but {} weren't restored",
+                        missing.join(", ")
+                    ),
+                )),
+            }
+        }
+    }
+
+    if options.gc {
+        for cid in &report.orphaned {
+            match client.storage.unpin(cid).await {
+                Ok(()) => report.unpinned.push(cid.clone()),
+                Err(e) => report.failed.push(failure(cid, e)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// The files an entry references, without those of its old versions.
+fn current_cids(payload: &Value) -> Vec<String> {
+    let mut payload = payload.clone();
+    if let Some(fields) = payload.as_object_mut() {
+        fields.remove("versions");
+    }
+    storage::referenced_cids(&payload)
+}
+
+/// Whether a daemon answers at `url`, with any status.
+async fn daemon_running(url: &str) -> Result<bool> {
+    let client = reqwest::Client::builder().timeout(DAEMON_TIMEOUT).build()?;
+    match client.get(url).send().await {
+        Ok(_) => Ok(true),
+        Err(e) if e.is_connect() => Ok(false),
+        Err(e) => Err(e).context(format!(
+            "couldn't tell whether the daemon at {url} is running"
+        )),
+    }
+}
+
+fn failure(id: &str, error: anyhow::Error) -> FsckFailure {
+    FsckFailure {
+        id: id.to_string(),
+        error: format!("{error:#}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::StubServer;
+
+    #[test]
+    fn versions_are_not_current_content() {
+        let payload = json!({
+            "media": [{ "cid": "a" }],
+            "versions": [{ "media": [{ "cid": "b" }] }],
+        });
+        assert_eq!(current_cids(&payload), ["a"]);
+    }
+
+    #[test]
+    fn restored_sidecars_are_not_orphaned() {
+        let payload = json!({
+            "cid": "video",
+            "sidecars": { "bundle": "bundle", "files": [{ "cid": "subtitles" }] },
+        });
+        // the files of a bundle are pinned on their own as well when they're stored again
+        let pins = HashSet::from(["video", "bundle", "subtitles"].map(String::from));
+        assert_eq!(storage::pinned_cids(&payload, &pins), ["video", "bundle"]);
+        // restores without bundles only pinned the files
+        let pins = HashSet::from(["video", "subtitles"].map(String::from));
+        assert_eq!(
+            storage::pinned_cids(&payload, &pins),
+            ["video", "subtitles"]
+        );
+    }
+
+    #[tokio::test]
+    async fn answering_daemons_are_running() {
+        let server = StubServer::start(vec![StubServer::response("401 Unauthorized", b"", true)]);
+        assert!(daemon_running(&server.url).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_not_daemons() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(!daemon_running(&format!("http://127.0.0.1:{port}"))
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,363 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use futures::{stream, StreamExt};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    api::Entry,
+    config, daemon,
+    download::{DownloadClient, SiteOptions},
+    network::{Component, Network},
+    snapshot, LocalClient,
+};
+
+/// Whether an entry's original link was still online when it was last checked
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginalStatus {
+    /// The post or file is still there
+    Available,
+    /// The site reports the post deleted, removed or missing
+    Gone,
+    /// The check was inconclusive, like when the site couldn't be reached or refused the request
+    Error,
+}
+
+impl std::fmt::Display for OriginalStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OriginalStatus::Available => "available",
+            OriginalStatus::Gone => "gone",
+            OriginalStatus::Error => "error",
+        })
+    }
+}
+
+impl std::str::FromStr for OriginalStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "available" => Ok(OriginalStatus::Available),
+            "gone" => Ok(OriginalStatus::Gone),
+            "error" => Ok(OriginalStatus::Error),
+            _ => bail!("unknown original status {s}, expected available, gone or error"),
+        }
+    }
+}
+
+/// Checks whether original links are still online, through the proxy and with the login configured
+/// for their site. Links are checked by their downloader if it has a check of its own, like yt-dlp,
+/// or else, or if that check fails, with a HEAD request. Clones are referenced counted.
+#[derive(Debug, Clone)]
+pub struct LinkChecker {
+    download: DownloadClient,
+    network: Network,
+    /// The longest a check by a downloader or a request may take
+    timeout: Duration,
+}
+
+impl LinkChecker {
+    /// Reads `LINK_CHECK_TIMEOUT_SECS` (default 30).
+    pub fn new(download: &DownloadClient, network: &Network) -> Result<Self> {
+        Ok(Self {
+            download: download.clone(),
+            network: network.clone(),
+            timeout: Duration::from_secs(config::var_or("LINK_CHECK_TIMEOUT_SECS", 30)?),
+        })
+    }
+
+    /// The status of the link. Links the downloader fails to check are requested instead.
+    /// Failed checks are logged and reported as `OriginalStatus::Error`.
+    pub async fn check(&self, url: &str) -> OriginalStatus {
+        let (downloader, options) = self.download.route(url);
+        let check = tokio::time::timeout(self.timeout, downloader.check(url, options));
+        let status = match check
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("check timed out")))
+        {
+            Ok(Some(status)) => Ok(status),
+            Ok(None) => self.request(url, options).await,
+            // downloaders fail on links they don't support, like yt-dlp on articles
+            Err(e) => {
+                info!(
+                    "{} couldn't check {url}, requesting it instead: {e:#}",
+                    downloader.name()
+                );
+                self.request(url, options).await
+            }
+        };
+        status.unwrap_or_else(|e| {
+            warn!("couldn't check {url}: {e:#}");
+            OriginalStatus::Error
+        })
+    }
+
+    /// Checks the link with a HEAD request, or a GET request for servers that don't answer HEAD requests.
+    async fn request(&self, url: &str, options: &SiteOptions) -> Result<OriginalStatus> {
+        let url = Url::parse(url).context("invalid link")?;
+        let client = self
+            .network
+            .client_builder(Component::Download, options.proxy.as_deref())?
+            .user_agent(snapshot::USER_AGENT)
+            .timeout(self.timeout)
+            .build()?;
+
+        let mut status = options
+            .authorize(client.head(url.clone()), &url)?
+            .send()
+            .await
+            .context("failed to reach link")?
+            .status();
+        if matches!(
+            status,
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            status = options
+                .authorize(client.get(url.clone()), &url)?
+                .send()
+                .await
+                .context("failed to reach link")?
+                .status();
+        }
+
+        match status {
+            status if status.is_success() => Ok(OriginalStatus::Available),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(OriginalStatus::Gone),
+            status => bail!("link returned {status}"),
+        }
+    }
+}
+
+/// How often the daemon checks the original links of all entries
+#[derive(Debug, Clone)]
+pub struct LinkMonitor {
+    /// How often the monitor runs, and how old a check may get before the link is checked again
+    pub interval: Duration,
+    /// How many links are checked at once
+    pub concurrency: usize,
+}
+
+impl LinkMonitor {
+    /// Reads `LINK_CHECK_INTERVAL_SECS` (default a day, 0 disables the monitor) and
+    /// `LINK_CHECK_CONCURRENCY` (default 4).
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            interval: Duration::from_secs(config::var_or(
+                "LINK_CHECK_INTERVAL_SECS",
+                24 * 60 * 60,
+            )?),
+            concurrency: config::var_or("LINK_CHECK_CONCURRENCY", 4)?,
+        })
+    }
+}
+
+/// The results of a run of `check_links`
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CheckReport {
+    pub checked: usize,
+    pub available: usize,
+    pub gone: usize,
+    pub error: usize,
+    /// The IDs of the entries whose originals were found gone by this run
+    pub newly_gone: Vec<String>,
+}
+
+/// Checks the original links of all entries that weren't checked within `max_age`, and records
+/// their `original_status` and `last_checked` time in the payload.
+pub async fn check_links(
+    client: &LocalClient,
+    max_age: Duration,
+    concurrency: usize,
+) -> Result<CheckReport> {
+    let now = daemon::unix_time();
+    let due: Vec<Entry> = client
+        .vector
+        .all(false)
+        .await?
+        .into_iter()
+        .map(|stored| stored.entry)
+        .filter(|entry| entry.payload["original_link"].is_string())
+        .filter(|entry| {
+            entry.payload["last_checked"]
+                .as_u64()
+                .is_none_or(|checked| checked + max_age.as_secs() <= now)
+        })
+        .collect();
+
+    let mut checks = stream::iter(due)
+        .map(|entry| async move {
+            let link = entry.payload["original_link"].as_str().unwrap_or_default();
+            let status = client.links.check(link).await;
+            (entry, status)
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut report = CheckReport::default();
+    while let Some((entry, status)) = checks.next().await {
+        let link = entry.payload["original_link"].as_str().unwrap_or_default();
+        if status == OriginalStatus::Gone && entry.payload["original_status"] != "gone" {
+            warn!("original of entry {} is gone: {link}", entry.id);
+            report.newly_gone.push(entry.id.clone());
+        }
+        client
+            .vector
+            .set_payload(
+                &entry.id,
+                json!({ "original_status": status, "last_checked": daemon::unix_time() }),
+            )
+            .await
+            .with_context(|| format!("failed to record status of entry {}", entry.id))?;
+
+        report.checked += 1;
+        match status {
+            OriginalStatus::Available => report.available += 1,
+            OriginalStatus::Gone => report.gone += 1,
+            OriginalStatus::Error => report.error += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// The entries a user may see, counted by the status of their original link
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct LinkReport {
+    pub available: usize,
+    pub gone: usize,
+    pub error: usize,
+    /// Entries whose original wasn't checked yet
+    pub unchecked: usize,
+    /// The entries with the requested status, most recently checked first
+    pub entries: Vec<Entry>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        download::{Downloader, RouteConfig},
+        testing::{FakeDownloader, StubServer},
+        transcode::FormatPolicy,
+    };
+
+    fn checker(check: Option<Option<OriginalStatus>>) -> LinkChecker {
+        let fake: Arc<dyn Downloader> = Arc::new(FakeDownloader {
+            name: "fake",
+            check,
+            ..Default::default()
+        });
+        let config = RouteConfig {
+            routes: vec![],
+            default: "fake".to_string(),
+            fallback: None,
+            options: SiteOptions::default(),
+        };
+        LinkChecker {
+            download: DownloadClient::with_downloaders(vec![fake], config, FormatPolicy::default())
+                .unwrap(),
+            network: Network::default(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    async fn request(status: &str) -> (Result<OriginalStatus>, Vec<String>) {
+        let server = StubServer::start(vec![StubServer::response(status, b"", true)]);
+        let result = checker(None)
+            .request(&format!("{}/post", server.url), &SiteOptions::default())
+            .await;
+        (result, server.requests())
+    }
+
+    #[tokio::test]
+    async fn available_links() {
+        let (status, requests) = request("200 OK").await;
+        assert_eq!(status.unwrap(), OriginalStatus::Available);
+        assert_eq!(requests, ["HEAD /post HTTP/1.1"]);
+        assert_eq!(
+            request("204 No Content").await.0.unwrap(),
+            OriginalStatus::Available
+        );
+    }
+
+    #[tokio::test]
+    async fn gone_links() {
+        assert_eq!(
+            request("404 Not Found").await.0.unwrap(),
+            OriginalStatus::Gone
+        );
+        assert_eq!(request("410 Gone").await.0.unwrap(), OriginalStatus::Gone);
+    }
+
+    #[tokio::test]
+    async fn retries_with_get_for_servers_without_head() {
+        for refused in ["405 Method Not Allowed", "501 Not Implemented"] {
+            let server = StubServer::start(vec![
+                StubServer::response(refused, b"", true),
+                StubServer::response("200 OK", b"page", true),
+            ]);
+            let status = checker(None)
+                .request(&server.url, &SiteOptions::default())
+                .await;
+            assert_eq!(status.unwrap(), OriginalStatus::Available);
+            assert_eq!(server.requests(), ["HEAD / HTTP/1.1", "GET / HTTP/1.1"]);
+        }
+
+        let server = StubServer::start(vec![
+            StubServer::response("405 Method Not Allowed", b"", true),
+            StubServer::response("404 Not Found", b"", true),
+        ]);
+        let status = checker(None)
+            .request(&server.url, &SiteOptions::default())
+            .await;
+        assert_eq!(status.unwrap(), OriginalStatus::Gone);
+    }
+
+    #[tokio::test]
+    async fn other_statuses_are_errors() {
+        for status in [
+            "403 Forbidden",
+            "429 Too Many Requests",
+            "500 Internal Server Error",
+        ] {
+            let (result, _) = request(status).await;
+            let error = result.unwrap_err().to_string();
+            assert!(error.contains(&status[..3]), "{error}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_links_are_errors() {
+        // nothing listens on the discard port
+        let status = checker(None)
+            .request("http://127.0.0.1:9/", &SiteOptions::default())
+            .await;
+        assert!(status.is_err());
+        assert_eq!(
+            checker(None).check("http://127.0.0.1:9/").await,
+            OriginalStatus::Error
+        );
+    }
+
+    #[tokio::test]
+    async fn prefers_the_downloaders_check() {
+        let server = StubServer::start(vec![]);
+        let checker = checker(Some(Some(OriginalStatus::Gone)));
+        assert_eq!(checker.check(&server.url).await, OriginalStatus::Gone);
+    }
+
+    #[tokio::test]
+    async fn requests_links_the_downloader_fails_to_check() {
+        for check in [None, Some(None)] {
+            let server = StubServer::start(vec![StubServer::response("410 Gone", b"", true)]);
+            let status = checker(check).check(&server.url).await;
+            assert_eq!(status, OriginalStatus::Gone);
+            assert_eq!(server.requests(), ["HEAD / HTTP/1.1"]);
+        }
+    }
+}
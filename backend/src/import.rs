@@ -0,0 +1,687 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    api::{AddLink, ClientApi, Visibility},
+    calendar,
+};
+
+/// The file formats links can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// CSV with a header row and a `link` (or `url`) column, optionally `description`, `tags` and `collection`
+    Csv,
+    /// One json object per line with the same fields as CSV, `tags` may be an array
+    Ndjson,
+    /// One URL per line, optionally followed by whitespace and a description
+    Urls,
+    /// A Twitter/X archive file like `tweets.js`, `like.js` or `bookmark.js`
+    Twitter,
+    /// A Reddit GDPR export CSV like `posts.csv`, `comments.csv` or `saved_posts.csv`
+    Reddit,
+    /// A Google Takeout YouTube playlist CSV, including watch later
+    Youtube,
+    /// A browser bookmarks HTML export, links are collected by their folder
+    Bookmarks,
+}
+
+impl std::str::FromStr for ImportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ImportFormat::Csv),
+            "ndjson" | "jsonl" => Ok(ImportFormat::Ndjson),
+            "urls" | "txt" => Ok(ImportFormat::Urls),
+            "twitter" | "x" | "js" => Ok(ImportFormat::Twitter),
+            "reddit" => Ok(ImportFormat::Reddit),
+            "youtube" => Ok(ImportFormat::Youtube),
+            "bookmarks" | "html" | "htm" => Ok(ImportFormat::Bookmarks),
+            _ => bail!(
+                "unknown import format {s}, expected csv, ndjson, urls, twitter, reddit, youtube or bookmarks"
+            ),
+        }
+    }
+}
+
+impl ImportFormat {
+    /// Guesses the format from the file extension. Reddit and YouTube exports are CSV files,
+    /// so their format has to be given explicitly.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .context("can't guess the import format without a file extension")?
+            .parse()
+    }
+}
+
+/// The outcome of importing one link, as recorded in the progress journal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct JournalEntry {
+    link: String,
+    /// The ID of the new entry if the import succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A link that failed to import
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportFailure {
+    pub link: String,
+    pub error: String,
+}
+
+/// The summary of an import run
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    /// The links in the import file
+    pub total: usize,
+    pub imported: usize,
+    /// Links that a previous run already imported
+    pub skipped: usize,
+    /// Repeated links within the file, which are only imported once
+    #[serde(default)]
+    pub duplicates: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// Reads the links to add from an import file. Records without a visibility get the given one,
+/// and records without a description are described by their link.
+pub fn read_records(
+    path: &Path,
+    format: ImportFormat,
+    visibility: Visibility,
+) -> Result<Vec<AddLink>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open import file {}", path.display()))?;
+
+    let records = match format {
+        ImportFormat::Csv => csv::Reader::from_reader(file)
+            .deserialize::<Record>()
+            .enumerate()
+            .map(|(i, record)| record.with_context(|| format!("invalid CSV record {}", i + 1)))
+            .collect::<Result<Vec<_>>>()?,
+        ImportFormat::Ndjson => BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+            .map(|(i, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("invalid json on line {}", i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        ImportFormat::Urls => BufReader::new(file)
+            .lines()
+            .filter(|line| {
+                line.as_ref()
+                    .map(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                    .unwrap_or(true)
+            })
+            .map(|line| {
+                let line = line?;
+                let (link, description) = line
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((line.trim(), ""));
+                Ok(Record {
+                    link: link.to_string(),
+                    description: Some(description.trim().to_string()),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        ImportFormat::Twitter => twitter_records(&std::io::read_to_string(file)?)?,
+        ImportFormat::Reddit => csv::Reader::from_reader(file)
+            .deserialize::<RedditRecord>()
+            .enumerate()
+            .map(|(i, record)| {
+                Ok(record
+                    .with_context(|| format!("invalid Reddit record {}", i + 1))?
+                    .into())
+            })
+            .collect::<Result<Vec<_>>>()?,
+        ImportFormat::Youtube => youtube_records(&std::io::read_to_string(file)?)?,
+        ImportFormat::Bookmarks => bookmark_records(&std::io::read_to_string(file)?),
+    };
+
+    Ok(records
+        .into_iter()
+        .map(|record| record.into_add_link(visibility))
+        .collect())
+}
+
+/// A record of a CSV or NDJSON import file
+#[derive(Deserialize, Debug, Default)]
+struct Record {
+    #[serde(alias = "url")]
+    link: String,
+    #[serde(default)]
+    description: Option<String>,
+    /// Either a list or a string of tags separated by commas or semicolons
+    #[serde(default)]
+    tags: Option<Value>,
+    #[serde(default)]
+    collection: Option<String>,
+    #[serde(default)]
+    visibility: Option<Visibility>,
+    /// Unix timestamp in seconds of the original post
+    #[serde(default)]
+    posted_at: Option<u64>,
+}
+
+impl Record {
+    fn into_add_link(self, visibility: Visibility) -> AddLink {
+        let tags = match self.tags {
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .filter_map(|t| t.as_str())
+                .map(str::to_string)
+                .collect(),
+            Some(Value::String(tags)) => tags
+                .split([',', ';'])
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            _ => vec![],
+        };
+        let description = self
+            .description
+            .filter(|d| !d.trim().is_empty())
+            .unwrap_or_else(|| self.link.clone());
+
+        AddLink {
+            tags,
+            collection: self.collection.filter(|c| !c.trim().is_empty()),
+            visibility: self.visibility.unwrap_or(visibility),
+            posted_at: self.posted_at,
+            ..AddLink::new(self.link.trim(), &description)
+        }
+    }
+}
+
+/// Reads a Twitter/X archive file. These assign a json array of single-key objects to a global variable,
+/// like `window.YTD.tweets.part0 = [{"tweet": {...}}]`.
+fn twitter_records(data: &str) -> Result<Vec<Record>> {
+    let json = match data.trim_start().strip_prefix("window.") {
+        Some(assignment) => assignment
+            .split_once('=')
+            .context("invalid Twitter archive file")?
+            .1
+            .trim()
+            .trim_end_matches(';'),
+        None => data,
+    };
+    let items: Vec<Value> = serde_json::from_str(json).context("invalid Twitter archive file")?;
+
+    items
+        .iter()
+        .map(|item| {
+            // tweets have the API's fields, likes and bookmarks are camel cased
+            let post = item
+                .as_object()
+                .and_then(|item| item.values().next())
+                .context("invalid Twitter archive item")?;
+            let id = post["id_str"]
+                .as_str()
+                .or(post["tweetId"].as_str())
+                .context("Twitter archive item without a tweet ID")?;
+            let link = match post["expandedUrl"].as_str() {
+                Some(url) => url.to_string(),
+                None => format!("https://twitter.com/i/web/status/{id}"),
+            };
+            let text = post["full_text"]
+                .as_str()
+                .or(post["fullText"].as_str())
+                .or(post["text"].as_str());
+
+            Ok(Record {
+                link,
+                description: text.map(unescape_html),
+                posted_at: post["created_at"].as_str().and_then(parse_twitter_time),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// A row of a Reddit GDPR export CSV, saved posts and comments only have the permalink
+#[derive(Deserialize, Debug)]
+struct RedditRecord {
+    permalink: String,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+impl From<RedditRecord> for Record {
+    fn from(record: RedditRecord) -> Self {
+        let link = match record.permalink.starts_with('/') {
+            true => format!("https://www.reddit.com{}", record.permalink),
+            false => record.permalink,
+        };
+        let text = [record.title, record.body]
+            .into_iter()
+            .flatten()
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        Record {
+            link,
+            description: Some(text),
+            posted_at: record.date.as_deref().and_then(parse_timestamp),
+            ..Default::default()
+        }
+    }
+}
+
+/// Reads a Google Takeout YouTube playlist CSV. Older exports start with a section describing the
+/// playlist, the videos follow a `Video Id,Playlist Video Creation Timestamp` header.
+fn youtube_records(data: &str) -> Result<Vec<Record>> {
+    let lines: Vec<_> = data.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| {
+            line.trim_start_matches('\u{feff}')
+                .to_ascii_lowercase()
+                .starts_with("video id")
+        })
+        .context("no videos in YouTube playlist export")?;
+
+    let videos = lines[start..].join("\n");
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(videos.as_bytes());
+
+    let mut records = vec![];
+    for row in reader.records() {
+        let row = row.context("invalid YouTube playlist record")?;
+        let Some(id) = row.get(0).map(str::trim).filter(|id| !id.is_empty()) else {
+            continue;
+        };
+        records.push(Record {
+            link: format!("https://www.youtube.com/watch?v={id}"),
+            posted_at: row.get(1).and_then(parse_timestamp),
+            ..Default::default()
+        });
+    }
+    Ok(records)
+}
+
+/// Reads a browser bookmarks HTML export in the Netscape bookmark file format.
+/// The folder a bookmark is in becomes its collection.
+fn bookmark_records(data: &str) -> Vec<Record> {
+    static TOKENS: OnceLock<Regex> = OnceLock::new();
+    static ATTRIBUTES: OnceLock<Regex> = OnceLock::new();
+    let tokens = TOKENS.get_or_init(|| {
+        Regex::new(r"(?is)<h3[^>]*>(.*?)</h3>|<a\s([^>]*)>(.*?)</a>|</dl>").unwrap()
+    });
+    let attributes =
+        ATTRIBUTES.get_or_init(|| Regex::new(r#"(?i)([a-z_]+)\s*=\s*"([^"]*)""#).unwrap());
+
+    let mut folders: Vec<String> = vec![];
+    let mut records = vec![];
+    for token in tokens.captures_iter(data) {
+        if let Some(folder) = token.get(1) {
+            folders.push(unescape_html(folder.as_str().trim()));
+        } else if let (Some(attrs), Some(title)) = (token.get(2), token.get(3)) {
+            let attr = |name: &str| {
+                attributes
+                    .captures_iter(attrs.as_str())
+                    .find(|a| a[1].eq_ignore_ascii_case(name))
+                    .map(|a| unescape_html(&a[2]))
+            };
+            let Some(link) = attr("href").filter(|link| link.starts_with("http")) else {
+                continue;
+            };
+
+            records.push(Record {
+                link,
+                description: Some(unescape_html(title.as_str().trim())),
+                tags: attr("tags").map(Value::String),
+                collection: folders.last().cloned(),
+                posted_at: attr("add_date").and_then(|date| date.parse().ok()),
+                ..Default::default()
+            });
+        } else {
+            // the end of a folder's list
+            folders.pop();
+        }
+    }
+    records
+}
+
+/// Decodes the HTML entities found in exported titles and post texts.
+pub(crate) fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parses timestamps like `2021-03-04 12:34:56 UTC`, `2021-03-04T12:34:56.789+01:00` or `2021-03-04`.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    static FORMAT: OnceLock<Regex> = OnceLock::new();
+    let format = FORMAT.get_or_init(|| {
+        Regex::new(r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?)?\s*(Z|UTC|[+-]\d{2}:?\d{2})?$")
+            .unwrap()
+    });
+
+    let parts = format.captures(timestamp.trim())?;
+    let number = |i: usize| parts.get(i).map_or(Some(0), |p| p.as_str().parse().ok());
+    let utc = calendar::utc_timestamp(
+        parts[1].parse().ok()?,
+        number(2)?,
+        number(3)?,
+        number(4)?,
+        number(5)?,
+        number(6)?,
+    )?;
+    apply_offset(utc, parts.get(7).map_or("Z", |p| p.as_str()))
+}
+
+/// Parses the timestamps of the Twitter API like `Wed Oct 10 20:19:24 +0000 2018`.
+fn parse_twitter_time(timestamp: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let [_, month, day, time, offset, year] = timestamp
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .try_into()
+        .ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let mut time = time.split(':').map(|n| n.parse().ok());
+    let utc = calendar::utc_timestamp(
+        year.parse().ok()?,
+        month,
+        day.parse().ok()?,
+        time.next()??,
+        time.next()??,
+        time.next()??,
+    )?;
+    apply_offset(utc, offset)
+}
+
+/// Converts a local timestamp with a UTC offset like `+0100`, `-05:00`, `Z` or `UTC` to UTC.
+fn apply_offset(timestamp: u64, offset: &str) -> Option<u64> {
+    if offset == "Z" || offset == "UTC" {
+        return Some(timestamp);
+    }
+
+    let (sign, offset) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+        (Some(offset), _) => (1, offset),
+        (_, Some(offset)) => (-1, offset),
+        _ => return None,
+    };
+    let digits = offset.replace(':', "");
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..)?.parse().ok()?;
+    let seconds = sign * (hours * 60 + minutes) * 60;
+    u64::try_from(timestamp as i64 - seconds).ok()
+}
+
+/// The progress journal kept next to an import file
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut journal = path.as_os_str().to_owned();
+    journal.push(".journal");
+    journal.into()
+}
+
+/// The summary report written next to an import file
+pub fn report_path(path: &Path) -> PathBuf {
+    let mut report = path.as_os_str().to_owned();
+    report.push(".report.json");
+    report.into()
+}
+
+/// Adds the links with up to `concurrency` adds in flight.
+///
+/// Every outcome is appended to the journal as it happens, and links the journal records as
+/// imported are skipped, so an interrupted import can be resumed by running it again.
+pub async fn import(
+    client: &dyn ClientApi,
+    records: Vec<AddLink>,
+    journal: &Path,
+    concurrency: usize,
+) -> Result<ImportReport> {
+    let mut imported = HashSet::new();
+    if journal.exists() {
+        let file = std::fs::File::open(journal).context("failed to open import journal")?;
+        for line in BufReader::new(file).lines() {
+            let entry: JournalEntry =
+                serde_json::from_str(&line?).context("invalid import journal")?;
+            if entry.id.is_some() {
+                imported.insert(entry.link);
+            }
+        }
+    }
+
+    let mut report = ImportReport {
+        total: records.len(),
+        ..Default::default()
+    };
+    let mut journal = std::fs::File::options()
+        .append(true)
+        .create(true)
+        .open(journal)
+        .context("failed to open import journal")?;
+
+    let mut pending = vec![];
+    let mut seen = HashSet::new();
+    for record in records {
+        if imported.contains(&record.link) {
+            report.skipped += 1;
+        } else if seen.insert(record.link.clone()) {
+            pending.push(record);
+        } else {
+            report.duplicates += 1;
+        }
+    }
+
+    let mut results = futures::stream::iter(pending)
+        .map(|record| async move {
+            let result = client.add_link(&record).await;
+            (record.link, result)
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    while let Some((link, result)) = results.next().await {
+        let entry = match result {
+            Ok(entry) => {
+                info!("imported {link} as {}", entry.id);
+                report.imported += 1;
+                JournalEntry {
+                    link,
+                    id: Some(entry.id),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                warn!("failed to import {link}: {e:#}");
+                report.failed.push(ImportFailure {
+                    link: link.clone(),
+                    error: format!("{e:#}"),
+                });
+                JournalEntry {
+                    link,
+                    id: None,
+                    error: Some(format!("{e:#}")),
+                }
+            }
+        };
+        writeln!(&mut journal, "{}", serde_json::to_string(&entry)?)
+            .context("failed to write import journal")?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("2021-03-04"), Some(1614816000));
+        assert_eq!(parse_timestamp("2021-03-04 12:34:56 UTC"), Some(1614861296));
+        assert_eq!(parse_timestamp("2021-03-04T12:34:56Z"), Some(1614861296));
+        assert_eq!(
+            parse_timestamp("2021-03-04T13:34:56.789+01:00"),
+            Some(1614861296)
+        );
+        assert_eq!(
+            parse_timestamp("2021-03-04T07:34:56-0500"),
+            Some(1614861296)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(parse_timestamp("2021-13-04"), None);
+        assert_eq!(parse_timestamp("2021-03-04T25:00:00Z"), None);
+        assert_eq!(parse_timestamp("1969-12-31"), None);
+    }
+
+    #[test]
+    fn parses_twitter_times() {
+        assert_eq!(
+            parse_twitter_time("Wed Oct 10 20:19:24 +0000 2018"),
+            Some(1539202764)
+        );
+        assert_eq!(
+            parse_twitter_time("Wed Oct 10 22:19:24 +0200 2018"),
+            Some(1539202764)
+        );
+        assert_eq!(parse_twitter_time("Wed Foo 10 20:19:24 +0000 2018"), None);
+        assert_eq!(parse_twitter_time("Wed Oct 10 20:19 +0000 2018"), None);
+        assert_eq!(parse_twitter_time("Wed Oct 10 20:19:24 2018"), None);
+    }
+
+    #[test]
+    fn rejects_offsets_without_a_sign() {
+        assert_eq!(parse_twitter_time("Wed Oct 10 20:19:24 é000 2018"), None);
+        assert_eq!(parse_twitter_time("Wed Oct 10 20:19:24 0000 2018"), None);
+        assert_eq!(apply_offset(100, "é"), None);
+        assert_eq!(apply_offset(100, ""), None);
+    }
+
+    #[test]
+    fn reads_twitter_archives() {
+        let data = r#"window.YTD.tweets.part0 = [
+            {"tweet": {"id_str": "1", "full_text": "a &amp; b", "created_at": "Wed Oct 10 20:19:24 +0000 2018"}},
+            {"like": {"tweetId": "2", "expandedUrl": "https://twitter.com/someone/status/2"}}
+        ];"#;
+        let records = twitter_records(data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].link, "https://twitter.com/i/web/status/1");
+        assert_eq!(records[0].description.as_deref(), Some("a & b"));
+        assert_eq!(records[0].posted_at, Some(1539202764));
+        assert_eq!(records[1].link, "https://twitter.com/someone/status/2");
+        assert_eq!(records[1].description, None);
+    }
+
+    #[test]
+    fn reads_reddit_records() {
+        let data = "id,permalink,date,ip,subreddit,gildings,title,url,body\n\
+            abc,/r/rust/comments/abc/title/,2021-03-04 12:34:56 UTC,,rust,0,A title,,Some text\n\
+            def,https://www.reddit.com/r/rust/comments/def/,,,rust,0,,,\n";
+        let records: Vec<Record> = csv::Reader::from_reader(data.as_bytes())
+            .deserialize::<RedditRecord>()
+            .map(|record| record.unwrap().into())
+            .collect();
+        assert_eq!(
+            records[0].link,
+            "https://www.reddit.com/r/rust/comments/abc/title/"
+        );
+        assert_eq!(
+            records[0].description.as_deref(),
+            Some("A title\n\nSome text")
+        );
+        assert_eq!(records[0].posted_at, Some(1614861296));
+        assert_eq!(
+            records[1].link,
+            "https://www.reddit.com/r/rust/comments/def/"
+        );
+        assert_eq!(records[1].description.as_deref(), Some(""));
+        assert_eq!(records[1].posted_at, None);
+    }
+
+    #[test]
+    fn reads_youtube_playlists() {
+        let data = "\u{feff}Playlist Id,Channel Id,Time Created\n\
+            PL1,UC1,2021-03-04 12:34:56 UTC\n\
+            \n\
+            Video Id,Playlist Video Creation Timestamp\n\
+            dQw4w9WgXcQ,2021-03-04T12:34:56+00:00\n\
+            ,\n\
+            abc123,\n";
+        let records = youtube_records(data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].link,
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+        assert_eq!(records[0].posted_at, Some(1614861296));
+        assert_eq!(records[1].link, "https://www.youtube.com/watch?v=abc123");
+        assert_eq!(records[1].posted_at, None);
+
+        assert!(youtube_records("Playlist Id\nPL1\n").is_err());
+    }
+
+    #[test]
+    fn reads_bookmarks_into_their_folders() {
+        let data = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<DL><p>
+    <DT><H3>Videos &amp; clips</H3>
+    <DL><p>
+        <DT><A HREF="https://example.com/a" ADD_DATE="1614861296" TAGS="one,two">First</A>
+        <DT><H3>Nested</H3>
+        <DL><p>
+            <DT><A HREF="https://example.com/b">Second</A>
+        </DL><p>
+        <DT><A HREF="https://example.com/c">Third</A>
+    </DL><p>
+    <DT><A HREF="javascript:alert(1)">Bookmarklet</A>
+    <DT><A HREF="https://example.com/d">Top level</A>
+</DL><p>"#;
+        let records = bookmark_records(data);
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.link.as_str(), r.collection.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("https://example.com/a", Some("Videos & clips")),
+                ("https://example.com/b", Some("Nested")),
+                ("https://example.com/c", Some("Videos & clips")),
+                ("https://example.com/d", None),
+            ]
+        );
+        assert_eq!(records[0].description.as_deref(), Some("First"));
+        assert_eq!(records[0].posted_at, Some(1614861296));
+        assert_eq!(records[0].tags, Some(Value::String("one,two".into())));
+    }
+}
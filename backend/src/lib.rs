@@ -1,15 +1,45 @@
 /// Core client interface
 pub mod api;
+/// Archive export and restore
+pub mod archive;
+/// API token authentication for the daemon
+pub mod auth;
+/// Conversions between unix timestamps and UTC dates
+pub mod calendar;
 /// Top-level client for logical operations
 pub mod client;
+/// Environment configuration helpers
+pub mod config;
 /// REST interface to client
 pub mod daemon;
 /// File download client
 pub mod download;
 /// Description embedding client
 pub mod embeddings;
+/// Integrity checks of stored files against entries
+pub mod fsck;
+/// Link health monitoring of archived originals
+pub mod health;
+/// Bulk import of links from files
+pub mod import;
+/// Proxy and TLS configuration of outbound connections
+pub mod network;
+/// Thumbnail and preview generation
+pub mod preview;
+/// Request rate limits and embedding spend quotas
+pub mod quota;
+/// Static HTML export of the archive
+pub mod site;
+/// Web page snapshots for links without media
+pub mod snapshot;
 /// File storage client
 pub mod storage;
+#[cfg(test)]
+mod testing;
+/// Download format policy and conversion
+pub mod transcode;
+/// Server-rendered web UI of the daemon
+pub mod ui;
 /// Vector database client
 pub mod vector;
 
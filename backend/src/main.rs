@@ -1,7 +1,16 @@
-use std::io::read_to_string;
+use std::{io::read_to_string, path::PathBuf};
 
 use anyhow::{Context, Result};
-use backend::{api::*, client::RemoteClient, LocalClient};
+use backend::{
+    api::*,
+    archive,
+    auth::TokenStore,
+    client::RemoteClient,
+    fsck::{self, FsckOptions},
+    health::{self, LinkMonitor, OriginalStatus},
+    import::{self, ImportFormat},
+    site, LocalClient,
+};
 use clap::*;
 use tracing::warn;
 use tracing_subscriber::EnvFilter;
@@ -16,11 +25,122 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Add a social media link to the archive
-    Add { link: String },
+    Add {
+        link: String,
+        /// Who may see the entry: private, team or public
+        #[arg(long, default_value_t)]
+        visibility: Visibility,
+    },
+    /// Adds the links listed in a CSV, NDJSON or plain URL list file, or in a platform data export
+    ///
+    /// Progress is journaled next to the file, running the import again resumes it.
+    Import {
+        file: PathBuf,
+        /// csv, ndjson, urls, twitter, reddit, youtube or bookmarks, guessed from the file extension by default
+        #[arg(long)]
+        format: Option<ImportFormat>,
+        /// How many links to add at once
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Who may see entries that don't specify it: private, team or public
+        #[arg(long, default_value_t)]
+        visibility: Visibility,
+    },
     /// Search the archive for description
     Search {},
+    /// Changes the owner, visibility, description, tags or collection of an entry
+    Update {
+        id: String,
+        /// The token ID of the new owner, only admins may reassign entries
+        #[arg(long)]
+        owner: Option<String>,
+        /// Who may see the entry: private, team or public
+        #[arg(long)]
+        visibility: Option<Visibility>,
+        #[arg(long)]
+        description: Option<String>,
+        /// Replaces the tags, separated by commas
+        #[arg(long, value_delimiter = ',')]
+        tags: Option<Vec<String>>,
+        #[arg(long)]
+        collection: Option<String>,
+    },
+    /// Downloads the link of an entry again, keeping the previous content as a version if it changed
+    Refresh { id: String },
+    /// Lists the versions of an entry's content, oldest first
+    Versions { id: String },
+    /// Shows the embedding tokens consumed and the daily budgets
+    Usage {},
+    /// Counts entries by the status of their original link and lists those with the given status
+    Originals {
+        /// available, gone or error
+        #[arg(long, default_value_t = OriginalStatus::Gone)]
+        status: OriginalStatus,
+    },
+    /// Checks whether the original links of the entries are still online, as the daemon does periodically
+    CheckLinks {
+        /// Also checks links that were checked within `LINK_CHECK_INTERVAL_SECS`
+        #[arg(long)]
+        all: bool,
+    },
     /// Runs a daemon that provides a HTTP REST interface
     Daemon {},
+    /// Exports all entries and their stored files to a tarball
+    Export {
+        file: PathBuf,
+        /// Include the embedding vectors, otherwise restoring embeds the descriptions again
+        #[arg(long)]
+        vectors: bool,
+    },
+    /// Restores the entries and files of a tarball made by export
+    ImportArchive { file: PathBuf },
+    /// Writes a static website of the archive to a directory
+    ExportSite {
+        dir: PathBuf,
+        /// The least shared entries to include: public, team or private
+        #[arg(long, default_value_t = Visibility::Public)]
+        include: Visibility,
+    },
+    /// Checks that every file the entries reference is pinned in storage and every pin is referenced,
+    /// and repairs what's wrong if asked to
+    ///
+    /// Exits with status 1 if anything was found wrong, even if it was repaired.
+    Fsck {
+        /// Also reads every referenced file and checks that it hashes to its CID, which reads the whole archive
+        #[arg(long)]
+        verify: bool,
+        /// Pins files that aren't pinned again, fetching them from the IPFS network if needed
+        #[arg(long)]
+        repin: bool,
+        /// Downloads the links of entries with files that are still missing or damaged again
+        #[arg(long)]
+        redownload: bool,
+        /// Unpins files no entry references, so the IPFS node's garbage collection frees them.
+        /// Only use this if the IPFS node stores nothing but the archive. Refuses to run while the daemon at
+        /// `API_URL`, or else localhost, is up, since adds in progress pin their files before their entries exist
+        #[arg(long)]
+        gc: bool,
+    },
+    /// Manages the API tokens accepted by the daemon
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommands {
+    /// Creates a token and prints its secret
+    Create {
+        name: String,
+        /// Allow the token to manage entries of other users
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Revokes a token by ID or name
+    Revoke { token: String },
+    /// Lists the tokens
+    List {},
 }
 
 #[tokio::main]
@@ -35,38 +155,226 @@ async fn main() -> Result<()> {
 
     let args = Cli::parse();
 
-    let client: Box<dyn ClientApi> = if let Ok(url) = std::env::var("API_URL") {
-        Box::new(RemoteClient::new(&url))
-    } else {
-        Box::new(
-            LocalClient::new()
-                .await
-                .context("failed to create client")?,
-        )
-    };
-
     match args.command {
-        Commands::Add { link } => {
+        Commands::Add { link, visibility } => {
             println!("Enter description for this post:");
             let input = read_to_string(std::io::stdin())?;
 
-            client.add_link(&link, &input).await?;
+            let client = client().await?;
+            client
+                .add_link(&AddLink {
+                    visibility,
+                    ..AddLink::new(&link, &input)
+                })
+                .await?;
+        }
+        Commands::Import {
+            file,
+            format,
+            concurrency,
+            visibility,
+        } => {
+            let format = match format {
+                Some(format) => format,
+                None => ImportFormat::from_path(&file)?,
+            };
+            let records = import::read_records(&file, format, visibility)?;
+            let client = client().await?;
+            let report =
+                import::import(&*client, records, &import::journal_path(&file), concurrency)
+                    .await?;
+
+            let report_path = import::report_path(&file);
+            std::fs::write(&report_path, serde_json::to_vec_pretty(&report)?)
+                .with_context(|| format!("failed to write report {}", report_path.display()))?;
+            println!(
+                "Imported {} of {} links, {} already imported, {} repeated, {} failed. Report written to {}",
+                report.imported,
+                report.total,
+                report.skipped,
+                report.duplicates,
+                report.failed.len(),
+                report_path.display()
+            );
         }
         Commands::Search {} => {
             println!("Enter description to search by:");
             let input = read_to_string(std::io::stdin())?;
 
+            let client = client().await?;
             let results = client.search(&input).await?;
             println!("{results}");
         }
+        Commands::Update {
+            id,
+            owner,
+            visibility,
+            description,
+            tags,
+            collection,
+        } => {
+            let update = EntryUpdate {
+                added_by: owner,
+                visibility,
+                description,
+                tags,
+                collection,
+            };
+            let client = client().await?;
+            let entry = client.update_entry(&id, &update).await?;
+            println!("{}", serde_json::to_string_pretty(&entry)?);
+        }
+        Commands::Refresh { id } => {
+            let client = client().await?;
+            let result = client.refresh_entry(&id).await?;
+            match result.changed {
+                true => println!("The content changed, the previous content was kept as a version"),
+                false => println!("The content didn't change"),
+            }
+            println!("{}", serde_json::to_string_pretty(&result.entry)?);
+        }
+        Commands::Versions { id } => {
+            let client = client().await?;
+            let versions = client.versions(&id).await?;
+            println!("{}", serde_json::to_string_pretty(&versions)?);
+        }
+        Commands::Usage {} => {
+            let client = client().await?;
+            println!("{}", serde_json::to_string_pretty(&client.usage().await?)?);
+        }
+        Commands::Originals { status } => {
+            let client = client().await?;
+            let report = client.link_report(status).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Commands::CheckLinks { all } => {
+            let client = local_client().await?;
+            let monitor = LinkMonitor::from_env()?;
+            let max_age = match all {
+                true => Default::default(),
+                false => monitor.interval,
+            };
+            let report = health::check_links(&client, max_age, monitor.concurrency).await?;
+            println!(
+                "Checked {} original links: {} available, {} gone ({} newly), {} failed",
+                report.checked,
+                report.available,
+                report.gone,
+                report.newly_gone.len(),
+                report.error
+            );
+            for id in report.newly_gone {
+                println!("{id}");
+            }
+        }
         Commands::Daemon {} => {
-            LocalClient::new()
-                .await
-                .context("failed to create local client")?
-                .daemonize()
-                .await?;
+            local_client().await?.daemonize().await?;
+        }
+        Commands::Export { file, vectors } => {
+            let client = local_client().await?;
+            let report = archive::export(&client, &file, vectors).await?;
+            println!(
+                "Exported {} entries and {} files to {}, {} files failed",
+                report.entries,
+                report.blobs,
+                file.display(),
+                report.failed.len()
+            );
+        }
+        Commands::ImportArchive { file } => {
+            let client = local_client().await?;
+            let report = archive::restore(&client, &file).await?;
+            println!(
+                "Restored {} entries and {} files, {} entries failed",
+                report.entries,
+                report.blobs,
+                report.failed.len()
+            );
+        }
+        Commands::ExportSite { dir, include } => {
+            let client = local_client().await?;
+            let report = site::export_site(&client, &dir, include).await?;
+            println!(
+                "Exported {} entries and {} files to {}, {} files failed",
+                report.entries,
+                report.blobs,
+                dir.display(),
+                report.failed.len()
+            );
+        }
+        Commands::Fsck {
+            verify,
+            repin,
+            redownload,
+            gc,
+        } => {
+            let client = local_client().await?;
+            let options = FsckOptions {
+                verify,
+                repin,
+                redownload,
+                gc,
+            };
+            let report = fsck::fsck(&client, options).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            println!(
+                "Checked {} files of {} entries: {} dangling, {} corrupt, {} orphaned pins. \
+                 Repinned {}, downloaded {} entries again, unpinned {}, {} failed",
+                report.files,
+                report.entries,
+                report.dangling.len(),
+                report.corrupt.len(),
+                report.orphaned.len(),
+                report.repinned.len(),
+                report.redownloaded.len(),
+                report.unpinned.len(),
+                report.failed.len()
+            );
+            if !report.is_clean() {
+                std::process::exit(1);
+            }
+        }
+        // tokens are managed in the daemon's token file, no client needed
+        Commands::Token { command } => {
+            let tokens = TokenStore::from_env()?;
+            match command {
+                TokenCommands::Create { name, admin } => {
+                    let (token, secret) = tokens.create(&name, admin)?;
+                    println!(
+                        "Created token {} ({}), its secret won't be shown again:",
+                        token.name, token.id
+                    );
+                    println!("{secret}");
+                }
+                TokenCommands::Revoke { token } => {
+                    let token = tokens.revoke(&token)?;
+                    println!("Revoked token {} ({})", token.name, token.id);
+                }
+                TokenCommands::List {} => {
+                    for token in tokens.list()? {
+                        let role = if token.admin { "admin" } else { "user" };
+                        println!("{}\t{}\t{role}", token.id, token.name);
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
+
+/// The client for the daemon at `API_URL`, or else a local client.
+async fn client() -> Result<Box<dyn ClientApi>> {
+    Ok(match std::env::var("API_URL") {
+        Ok(url) => Box::new(RemoteClient::new(&url)),
+        Err(_) => Box::new(local_client().await?),
+    })
+}
+
+/// A client for the local archive. Commands create at most one, since the embedding cache
+/// can only be opened once at a time.
+async fn local_client() -> Result<LocalClient> {
+    LocalClient::new()
+        .await
+        .context("failed to create local client")
+}
@@ -0,0 +1,204 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use reqwest::{Certificate, Client, ClientBuilder, Proxy};
+use tempdir::TempDir;
+use tracing::warn;
+
+use crate::config;
+
+/// A proxy setting that routes around the configured proxies
+pub const DIRECT: &str = "direct";
+
+/// The components that make outbound connections through their own proxy settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// yt-dlp, gallery-dl, file downloads and page snapshots
+    Download,
+    /// The OpenAI embeddings API
+    OpenAi,
+}
+
+/// Proxies and trusted certificates of outbound connections, shared by all reqwest clients
+/// and the downloader commands.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    /// Used by components without a proxy of their own
+    proxy: Option<String>,
+    download_proxy: Option<String>,
+    openai_proxy: Option<String>,
+    /// A PEM file of CA certificates trusted in addition to the system's
+    ca_bundle: Option<PathBuf>,
+    /// The system's certificates and those of `ca_bundle` in one file, for the downloader commands
+    command_bundle: Option<Arc<CommandBundle>>,
+}
+
+/// A CA bundle file that is removed when the last clone of the network settings is dropped
+struct CommandBundle(TempDir);
+
+impl CommandBundle {
+    /// Writes the system's certificates and the given bundle's to a new file, since the downloader
+    /// commands only read a single bundle.
+    fn new(ca_bundle: &Path) -> Result<Self> {
+        let mut pem = match openssl_probe::probe().cert_file {
+            Some(system) => std::fs::read_to_string(&system).with_context(|| {
+                format!("failed to read system certificates {}", system.display())
+            })?,
+            None => {
+                warn!("found no system certificates, downloader commands only trust CA_BUNDLE");
+                String::new()
+            }
+        };
+        if !pem.is_empty() && !pem.ends_with('\n') {
+            pem.push('\n');
+        }
+        pem += &std::fs::read_to_string(ca_bundle)
+            .with_context(|| format!("failed to read CA bundle {}", ca_bundle.display()))?;
+
+        let bundle = Self(TempDir::new("socialmediacerts")?);
+        std::fs::write(bundle.path(), pem).context("failed to write CA bundle")?;
+        Ok(bundle)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.0.path().join("ca-bundle.pem")
+    }
+}
+
+impl std::fmt::Debug for CommandBundle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CommandBundle").field(&self.path()).finish()
+    }
+}
+
+impl Network {
+    /// Reads `PROXY`, `DOWNLOAD_PROXY`, `OPENAI_PROXY` and `CA_BUNDLE`.
+    ///
+    /// Proxies are URLs like `http://proxy:3128` or `socks5://proxy:1080`. A component's proxy may be
+    /// `direct` to bypass `PROXY`. Qdrant and IPFS are always connected to directly and only trust the
+    /// system's certificates, as their clients support neither proxies nor added certificates.
+    pub fn from_env() -> Result<Self> {
+        let ca_bundle: Option<PathBuf> = config::var_opt("CA_BUNDLE")?;
+        let command_bundle = match &ca_bundle {
+            Some(ca_bundle) => Some(Arc::new(CommandBundle::new(ca_bundle)?)),
+            None => None,
+        };
+        let network = Self {
+            proxy: config::var_opt("PROXY")?,
+            download_proxy: config::var_opt("DOWNLOAD_PROXY")?,
+            openai_proxy: config::var_opt("OPENAI_PROXY")?,
+            ca_bundle,
+            command_bundle,
+        };
+        // fails early on invalid settings
+        network.client(Component::Download, None)?;
+        network.client(Component::OpenAi, None)?;
+        Ok(network)
+    }
+
+    /// The proxy of a component, or the given override such as a site's proxy. `None` for direct connections.
+    pub fn proxy<'a>(&'a self, component: Component, over: Option<&'a str>) -> Option<&'a str> {
+        let own = match component {
+            Component::Download => &self.download_proxy,
+            Component::OpenAi => &self.openai_proxy,
+        };
+        over.or(own.as_deref())
+            .or(self.proxy.as_deref())
+            .filter(|proxy| *proxy != DIRECT)
+    }
+
+    /// A reqwest client builder for the component, with its proxy or the override and the CA bundle.
+    pub fn client_builder(
+        &self,
+        component: Component,
+        proxy: Option<&str>,
+    ) -> Result<ClientBuilder> {
+        let mut builder = Client::builder();
+        builder = match self.proxy(component, proxy) {
+            Some(proxy) => builder.proxy(Proxy::all(proxy).context("invalid proxy")?),
+            // proxy env variables of the system don't apply either
+            None => builder.no_proxy(),
+        };
+        for certificate in self.certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(builder)
+    }
+
+    /// A reqwest client for the component, see `client_builder`.
+    pub fn client(&self, component: Component, proxy: Option<&str>) -> Result<Client> {
+        Ok(self.client_builder(component, proxy)?.build()?)
+    }
+
+    /// Configures a yt-dlp or gallery-dl command to use the download proxy or the override,
+    /// and to trust the CA bundle along with the system's certificates.
+    pub fn configure_command(&self, command: &mut Command, proxy: Option<&str>) {
+        let yt_dlp = command.get_program() == "yt-dlp";
+        match self.proxy(Component::Download, proxy) {
+            Some(proxy) => {
+                command.args(["--proxy", proxy]);
+            }
+            // an empty proxy makes yt-dlp ignore the system's proxy env variables
+            None if yt_dlp => {
+                command.args(["--proxy", ""]);
+            }
+            None => {}
+        }
+        if let Some(bundle) = &self.command_bundle {
+            // yt-dlp only reads the system's certificates when it's told not to use its own
+            if yt_dlp {
+                command.args(["--compat-options", "no-certifi"]);
+            }
+            // these replace the system's certificates, so the bundle includes them
+            command.env("SSL_CERT_FILE", bundle.path());
+            command.env("REQUESTS_CA_BUNDLE", bundle.path());
+        }
+    }
+
+    fn certificates(&self) -> Result<Vec<Certificate>> {
+        let Some(bundle) = &self.ca_bundle else {
+            return Ok(vec![]);
+        };
+        let pem = std::fs::read_to_string(bundle)
+            .with_context(|| format!("failed to read CA bundle {}", bundle.display()))?;
+
+        const END: &str = "-----END CERTIFICATE-----";
+        pem.split_inclusive(END)
+            .filter(|block| block.contains(END))
+            .map(|block| Certificate::from_pem(block.trim().as_bytes()))
+            .collect::<Result<_, _>>()
+            .context("invalid certificate in CA bundle")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socks_proxies_are_supported() {
+        let network = Network {
+            proxy: Some("socks5://127.0.0.1:1080".to_string()),
+            ..Default::default()
+        };
+        assert!(network.client(Component::Download, None).is_ok());
+    }
+
+    #[test]
+    fn command_bundles_keep_the_system_certificates() {
+        let dir = TempDir::new("networktest").unwrap();
+        let ca_bundle = dir.path().join("custom.pem");
+        std::fs::write(&ca_bundle, "custom certificate\n").unwrap();
+
+        let bundle = CommandBundle::new(&ca_bundle).unwrap();
+        let pem = std::fs::read_to_string(bundle.path()).unwrap();
+        assert!(pem.ends_with("custom certificate\n"));
+        if let Some(system) = openssl_probe::probe().cert_file {
+            assert!(pem.starts_with(&std::fs::read_to_string(system).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::*;
+use tokio::process::Command;
+
+use crate::config;
+
+/// Generates thumbnails and short previews of downloaded media with ffmpeg
+#[derive(Debug, Clone)]
+pub struct PreviewClient {
+    /// The width of thumbnails in pixels, the height keeps the aspect ratio
+    thumbnail_width: u32,
+    /// The width of previews in pixels
+    preview_width: u32,
+    /// The length of previews in seconds, 0 disables previews
+    preview_seconds: u32,
+}
+
+/// The files generated for a downloaded file
+#[derive(Debug, Clone, Default)]
+pub struct Previews {
+    /// A JPEG image, for videos and images
+    pub thumbnail: Option<PathBuf>,
+    /// A silent, low resolution MP4 of the start of a video
+    pub preview: Option<PathBuf>,
+}
+
+impl PreviewClient {
+    /// Reads `THUMBNAIL_WIDTH` (default 320), `PREVIEW_WIDTH` (default 240) and `PREVIEW_SECONDS` (default 6).
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            thumbnail_width: config::var_or("THUMBNAIL_WIDTH", 320)?,
+            preview_width: config::var_or("PREVIEW_WIDTH", 240)?,
+            preview_seconds: config::var_or("PREVIEW_SECONDS", 6)?,
+        })
+    }
+
+    /// Generates the thumbnail and preview of a downloaded file with the given content type next to it,
+    /// named after it. Content without visuals, like audio, gets neither.
+    pub async fn generate(&self, media: &Path, content_type: &str) -> Result<Previews> {
+        let mut previews = Previews::default();
+        let (thumbnail_filter, is_video) = match content_type.split('/').next() {
+            // picks a representative frame instead of a black first frame
+            Some("video") => (format!("thumbnail,scale={}:-2", self.thumbnail_width), true),
+            Some("image") => (
+                format!("scale='min({},iw)':-2", self.thumbnail_width),
+                false,
+            ),
+            _ => return Ok(previews),
+        };
+
+        let thumbnail = media.with_extension("thumbnail.jpg");
+        ffmpeg(
+            media,
+            &["-vf", &thumbnail_filter, "-frames:v", "1"],
+            &thumbnail,
+        )
+        .await
+        .context("failed to generate thumbnail")?;
+        previews.thumbnail = Some(thumbnail);
+
+        if is_video && self.preview_seconds > 0 {
+            let preview = media.with_extension("preview.mp4");
+            ffmpeg(
+                media,
+                &[
+                    "-t",
+                    &self.preview_seconds.to_string(),
+                    "-an",
+                    "-vf",
+                    &format!("scale={}:-2", self.preview_width),
+                    "-c:v",
+                    "libx264",
+                    "-preset",
+                    "veryfast",
+                    "-crf",
+                    "32",
+                    "-movflags",
+                    "+faststart",
+                ],
+                &preview,
+            )
+            .await
+            .context("failed to generate preview")?;
+            previews.preview = Some(preview);
+        }
+
+        Ok(previews)
+    }
+}
+
+/// Runs ffmpeg on the input with the given output options, overwriting the output file.
+/// ffmpeg is killed if the future is dropped.
+pub(crate) async fn ffmpeg(input: &Path, options: &[&str], output: &Path) -> Result<()> {
+    let exit = Command::new("ffmpeg")
+        .args(["-y", "-v", "error", "-i"])
+        .arg(input)
+        .args(options)
+        .arg(output)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .context("failed to run ffmpeg command")?;
+
+    ensure!(exit.success(), "ffmpeg command failed with {exit}");
+    Ok(())
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{error::InternalError, http::header, HttpResponse};
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{calendar::utc_date, config, embeddings::CacheStats};
+
+/// A token bucket that allows `limit` requests per minute, with bursts of up to `limit`.
+#[derive(Debug, Clone)]
+struct Bucket {
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: u32) -> Self {
+        Self {
+            available: limit as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket, returns how long until a request is allowed.
+    fn refill(&mut self, limit: u32) -> Duration {
+        let per_sec = limit as f64 / 60.0;
+        let now = Instant::now();
+        self.available = (self.available
+            + now.duration_since(self.updated).as_secs_f64() * per_sec)
+            .min(limit as f64);
+        self.updated = now;
+
+        if self.available >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.available) / per_sec)
+        }
+    }
+}
+
+/// Per-token and global request rate limits of the daemon. Clones are referenced counted.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Requests per minute allowed for each API token
+    per_token: Option<u32>,
+    /// Requests per minute allowed across all API tokens
+    global: Option<u32>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    global: Option<Bucket>,
+    per_token: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// Reads `RATE_LIMIT_PER_MINUTE` (default 120) and `GLOBAL_RATE_LIMIT_PER_MINUTE` (default unlimited),
+    /// a limit of 0 disables it.
+    pub fn from_env() -> Result<Self> {
+        let per_token = config::var_or("RATE_LIMIT_PER_MINUTE", 120)?;
+        let global = config::var_or("GLOBAL_RATE_LIMIT_PER_MINUTE", 0)?;
+        Ok(Self {
+            per_token: (per_token > 0).then_some(per_token),
+            global: (global > 0).then_some(global),
+            buckets: Default::default(),
+        })
+    }
+
+    /// Counts a request by the given user. If a limit was reached, the request isn't counted
+    /// and the time until it would be allowed is returned.
+    pub fn check(&self, user_id: &str) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { global, per_token } = &mut *buckets;
+
+        let global = self
+            .global
+            .map(|limit| (global.get_or_insert_with(|| Bucket::new(limit)), limit));
+        let per_token = self.per_token.map(|limit| {
+            let bucket = per_token
+                .entry(user_id.to_string())
+                .or_insert_with(|| Bucket::new(limit));
+            (bucket, limit)
+        });
+        let mut limited: Vec<_> = global.into_iter().chain(per_token).collect();
+
+        let wait = limited
+            .iter_mut()
+            .map(|(bucket, limit)| bucket.refill(*limit))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (bucket, _) in limited {
+            bucket.available -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned to requests over the rate limit
+pub fn too_many_requests(retry_after: Duration) -> actix_web::Error {
+    InternalError::from_response(
+        "rate limit exceeded",
+        HttpResponse::TooManyRequests()
+            .insert_header((
+                header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().to_string(),
+            ))
+            .json(json!({"error": "rate limit exceeded"})),
+    )
+    .into()
+}
+
+/// The embedding tokens consumed per day and per API token ID. Days are UTC dates like `2023-11-20`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(transparent)]
+pub struct Usage(pub BTreeMap<String, BTreeMap<String, u64>>);
+
+/// The embedding token usage as seen by one user, along with the budgets that apply to it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsageReport {
+    /// Today's UTC date
+    pub date: String,
+    /// Tokens consumed today by everyone
+    pub used_today: u64,
+    /// Tokens consumed today by the user
+    pub used_today_by_user: u64,
+    pub daily_budget: Option<u64>,
+    pub daily_budget_per_user: Option<u64>,
+    /// The history of tokens consumed, limited to the user's own usage for non-admins
+    pub daily: Usage,
+    /// `None` if the embedding cache is disabled
+    #[serde(default)]
+    pub embedding_cache: Option<CacheStats>,
+}
+
+/// Tracks the embedding tokens consumed, persisted to a json file, and enforces daily budgets.
+/// Clones are referenced counted.
+#[derive(Clone)]
+pub struct UsageTracker {
+    path: PathBuf,
+    daily_budget: Option<u64>,
+    daily_budget_per_user: Option<u64>,
+    usage: Arc<Mutex<Usage>>,
+    /// The estimated tokens of requests in progress per API token ID, counted against the budgets
+    /// so concurrent requests can't overshoot them
+    reserved: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+/// Estimated tokens reserved by `UsageTracker::check_budget` for a request in progress. Released when
+/// the consumed tokens are recorded, or when dropped because the request failed.
+#[must_use]
+pub struct Reservation {
+    tracker: UsageTracker,
+    user_id: String,
+    tokens: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reserved = self.tracker.reserved.lock().unwrap();
+        if let Some(user) = reserved.get_mut(&self.user_id) {
+            *user = user.saturating_sub(self.tokens);
+            if *user == 0 {
+                reserved.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+impl UsageTracker {
+    /// Uses the file at `USAGE_FILE`, or `usage.json` in the working directory, and the budgets in
+    /// `DAILY_EMBEDDING_TOKEN_BUDGET` and `DAILY_EMBEDDING_TOKEN_BUDGET_PER_USER` (default unlimited).
+    pub fn from_env() -> Result<Self> {
+        let path = config::var_or("USAGE_FILE", PathBuf::from("usage.json"))?;
+        let usage = match path.exists() {
+            true => serde_json::from_slice(
+                &std::fs::read(&path)
+                    .with_context(|| format!("failed to read usage file {}", path.display()))?,
+            )
+            .context("failed to parse usage file")?,
+            false => Usage::default(),
+        };
+
+        Ok(Self {
+            path,
+            daily_budget: config::var_opt("DAILY_EMBEDDING_TOKEN_BUDGET")?,
+            daily_budget_per_user: config::var_opt("DAILY_EMBEDDING_TOKEN_BUDGET_PER_USER")?,
+            usage: Arc::new(Mutex::new(usage)),
+            reserved: Default::default(),
+        })
+    }
+
+    /// Reserves the estimated tokens of a request for the user, or fails if today's global budget or the
+    /// user's budget is exhausted, counting the tokens reserved by requests in progress as used.
+    pub fn check_budget(&self, user_id: &str, estimate: u64) -> Result<Reservation> {
+        let usage = self.usage.lock().unwrap();
+        let mut reserved = self.reserved.lock().unwrap();
+        let today = usage.0.get(&today());
+
+        if let Some(budget) = self.daily_budget {
+            let used = today.map(|users| users.values().sum()).unwrap_or(0)
+                + reserved.values().sum::<u64>();
+            ensure!(
+                used < budget,
+                "the daily embedding token budget of {budget} is exhausted"
+            );
+        }
+        if let Some(budget) = self.daily_budget_per_user {
+            let used = today
+                .and_then(|users| users.get(user_id))
+                .copied()
+                .unwrap_or(0)
+                + reserved.get(user_id).copied().unwrap_or(0);
+            ensure!(
+                used < budget,
+                "your daily embedding token budget of {budget} is exhausted"
+            );
+        }
+
+        *reserved.entry(user_id.to_string()).or_default() += estimate;
+        Ok(Reservation {
+            tracker: self.clone(),
+            user_id: user_id.to_string(),
+            tokens: estimate,
+        })
+    }
+
+    /// Records tokens consumed by the user and persists the usage, releasing the tokens reserved for the
+    /// request in the same step.
+    pub fn record(
+        &self,
+        user_id: &str,
+        tokens: u64,
+        reservation: Option<Reservation>,
+    ) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        *usage
+            .0
+            .entry(today())
+            .or_default()
+            .entry(user_id.to_string())
+            .or_default() += tokens;
+        drop(reservation);
+
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&*usage)?)
+            .with_context(|| format!("failed to write usage file {}", self.path.display()))
+    }
+
+    /// Reports the usage seen by the user, admins see everyone's usage.
+    pub fn report(&self, user_id: &str, admin: bool) -> UsageReport {
+        let usage = self.usage.lock().unwrap();
+        let date = today();
+        let today = usage.0.get(&date);
+
+        let daily = match admin {
+            true => usage.clone(),
+            false => Usage(
+                usage
+                    .0
+                    .iter()
+                    .filter_map(|(day, users)| {
+                        let used = *users.get(user_id)?;
+                        Some((day.clone(), BTreeMap::from([(user_id.to_string(), used)])))
+                    })
+                    .collect(),
+            ),
+        };
+
+        UsageReport {
+            used_today: today.map(|users| users.values().sum()).unwrap_or(0),
+            used_today_by_user: today
+                .and_then(|users| users.get(user_id))
+                .copied()
+                .unwrap_or(0),
+            date,
+            daily_budget: self.daily_budget,
+            daily_budget_per_user: self.daily_budget_per_user,
+            daily,
+            embedding_cache: None,
+        }
+    }
+}
+
+/// Today's UTC date formatted as `YYYY-MM-DD`
+fn today() -> String {
+    utc_date(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(dir: &tempdir::TempDir, budget: Option<u64>, per_user: Option<u64>) -> UsageTracker {
+        UsageTracker {
+            path: dir.path().join("usage.json"),
+            daily_budget: budget,
+            daily_budget_per_user: per_user,
+            usage: Default::default(),
+            reserved: Default::default(),
+        }
+    }
+
+    #[test]
+    fn reservations_count_against_the_budget() {
+        let dir = tempdir::TempDir::new("usage").unwrap();
+        let usage = tracker(&dir, Some(100), None);
+
+        let first = usage.check_budget("a", 60).unwrap();
+        let second = usage.check_budget("b", 50).unwrap();
+        // the two requests in progress already reserved more than the budget
+        assert!(usage.check_budget("c", 1).is_err());
+
+        // a failed request releases its reservation
+        drop(second);
+        let third = usage.check_budget("c", 10).unwrap();
+
+        usage.record("a", 70, Some(first)).unwrap();
+        usage.record("c", 5, Some(third)).unwrap();
+        assert!(usage.reserved.lock().unwrap().is_empty());
+        assert_eq!(usage.report("a", true).used_today, 75);
+        assert!(usage.check_budget("c", 1).is_ok());
+    }
+
+    #[test]
+    fn per_user_budgets_only_count_the_user() {
+        let dir = tempdir::TempDir::new("usage").unwrap();
+        let usage = tracker(&dir, None, Some(10));
+
+        let _a = usage.check_budget("a", 10).unwrap();
+        assert!(usage.check_budget("a", 1).is_err());
+        assert!(usage.check_budget("b", 1).is_ok());
+    }
+}
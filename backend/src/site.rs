@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    api::Visibility,
+    archive::{self, ArchiveFailure, ArchiveReport},
+    calendar, storage, LocalClient,
+};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; max-width: 60rem; margin: 0 auto; padding: 1rem; }
+nav a { margin-right: 1rem; }
+video, audio, img { max-width: 100%; }
+iframe { width: 100%; height: 30rem; border: 1px solid #ccc; }
+ul.entries li { margin: 0.5rem 0; }
+.meta { color: #666; font-size: 0.9rem; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.25rem 1rem; }
+dd { margin: 0; overflow-wrap: anywhere; }
+pre { white-space: pre-wrap; }
+";
+
+/// An entry as shown on the site
+struct SiteEntry {
+    id: String,
+    payload: Value,
+    title: String,
+    /// When the original was posted, or else when it was archived
+    date: Option<u64>,
+    platform: String,
+    tags: Vec<String>,
+    /// The file names of the media items in `media/` and their content types, in order
+    media: Vec<(String, &'static str)>,
+}
+
+/// Writes a static website of the archive to the directory: a page per entry with its media,
+/// and index pages by date, platform and tag.
+///
+/// Media already in the directory isn't fetched again, so a site can be updated by exporting
+/// to the same directory. Only entries at least as widely shared as `include` are exported.
+pub async fn export_site(
+    client: &LocalClient,
+    dir: &Path,
+    include: Visibility,
+) -> Result<ArchiveReport> {
+    for subdir in ["entries", "media", "dates", "platforms", "tags"] {
+        std::fs::create_dir_all(dir.join(subdir))
+            .with_context(|| format!("failed to create {}", dir.join(subdir).display()))?;
+    }
+    std::fs::write(dir.join("style.css"), STYLE)?;
+
+    // media files are named by their CID, with an extension for their content type
+    let mut existing = HashMap::new();
+    for file in std::fs::read_dir(dir.join("media"))? {
+        let name = file?.file_name().to_string_lossy().to_string();
+        if let Some((cid, _)) = name.split_once('.') {
+            existing.insert(cid.to_string(), name.clone());
+        }
+    }
+
+    let mut report = ArchiveReport::default();
+    let mut entries = vec![];
+    for stored in client.vector.all(false).await? {
+        let payload = stored.entry.payload;
+        if visibility(&payload) < include {
+            continue;
+        }
+
+        // entries archived before media lists only have their main media
+        let cids: Vec<&str> = match payload["media"].as_array() {
+            Some(items) => items.iter().filter_map(|i| i["cid"].as_str()).collect(),
+            None => payload["cid"].as_str().into_iter().collect(),
+        };
+        let mut media = vec![];
+        for cid in cids {
+            match export_media(client, dir, cid, &mut existing).await {
+                Ok(file) => {
+                    report.blobs += 1;
+                    media.push(file);
+                }
+                Err(e) => {
+                    warn!("couldn't export {cid} of entry {}: {e:#}", stored.entry.id);
+                    report.failed.push(ArchiveFailure {
+                        id: cid.to_string(),
+                        error: format!("{e:#}"),
+                    });
+                }
+            }
+        }
+
+        let link = payload["original_link"].as_str().unwrap_or_default();
+        let description = payload["description"].as_str().unwrap_or_default().trim();
+        entries.push(SiteEntry {
+            id: stored.entry.id,
+            title: title(description, link),
+            date: payload["posted_at"]
+                .as_u64()
+                .or(payload["added_at"].as_u64()),
+            platform: platform(link),
+            tags: payload["tags"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|t| t.as_str())
+                .map(str::to_string)
+                .collect(),
+            media,
+            payload,
+        });
+    }
+    // newest first, undated entries last
+    entries.sort_by_key(|e| std::cmp::Reverse(e.date));
+
+    for entry in &entries {
+        std::fs::write(
+            dir.join("entries").join(format!("{}.html", entry.id)),
+            entry_page(entry),
+        )?;
+    }
+    report.entries = entries.len();
+
+    std::fs::write(
+        dir.join("index.html"),
+        page("Archive", "", &entry_list(entries.iter(), "")),
+    )?;
+
+    let by_date = group(&entries, |e| {
+        vec![e.date.map_or("undated".to_string(), |d| {
+            calendar::utc_date(d)[..7].to_string()
+        })]
+    });
+    write_index(dir, "dates", "By date", by_date)?;
+    write_index(
+        dir,
+        "platforms",
+        "By platform",
+        group(&entries, |e| vec![e.platform.clone()]),
+    )?;
+    write_index(dir, "tags", "By tag", group(&entries, |e| e.tags.clone()))?;
+
+    Ok(report)
+}
+
+/// Copies an entry's media to the site unless it's already there, returns its file name and content type.
+async fn export_media(
+    client: &LocalClient,
+    dir: &Path,
+    cid: &str,
+    existing: &mut HashMap<String, String>,
+) -> Result<(String, &'static str)> {
+    let media = dir.join("media");
+    let name = match existing.get(cid) {
+        Some(name) => name.clone(),
+        None => {
+            let download = media.join(cid);
+            archive::fetch_blob(client, cid, &download).await?;
+            let (_, extension) = storage::sniff_file(&download)?;
+            let name = format!("{cid}.{extension}");
+            std::fs::rename(&download, media.join(&name))?;
+            existing.insert(cid.to_string(), name.clone());
+            name
+        }
+    };
+
+    let (content_type, _) = storage::sniff_file(media.join(&name))?;
+    Ok((name, content_type))
+}
+
+/// Groups entries by the keys returned for each, keeping their order within groups.
+fn group(
+    entries: &[SiteEntry],
+    keys: impl Fn(&SiteEntry) -> Vec<String>,
+) -> BTreeMap<String, Vec<&SiteEntry>> {
+    let mut groups: BTreeMap<String, Vec<&SiteEntry>> = BTreeMap::new();
+    for entry in entries {
+        for key in keys(entry) {
+            groups.entry(key).or_default().push(entry);
+        }
+    }
+    groups
+}
+
+/// Writes a page per group to the subdirectory, and an index of the groups next to it.
+fn write_index(
+    dir: &Path,
+    subdir: &str,
+    title: &str,
+    groups: BTreeMap<String, Vec<&SiteEntry>>,
+) -> Result<()> {
+    let mut index = String::from("<ul>\n");
+    for (key, entries) in &groups {
+        let file = format!("{}.html", slug(key));
+        index.push_str(&format!(
+            "<li><a href=\"{subdir}/{file}\">{}</a> ({})</li>\n",
+            escape(key),
+            entries.len()
+        ));
+        std::fs::write(
+            dir.join(subdir).join(&file),
+            page(key, "../", &entry_list(entries.iter().copied(), "../")),
+        )?;
+    }
+    index.push_str("</ul>\n");
+
+    std::fs::write(dir.join(format!("{subdir}.html")), page(title, "", &index))?;
+    Ok(())
+}
+
+fn entry_page(entry: &SiteEntry) -> String {
+    let payload = &entry.payload;
+    let link = payload["original_link"].as_str().unwrap_or_default();
+
+    let media = match entry.media.is_empty() {
+        true => "<p>The media of this entry isn't available.</p>".to_string(),
+        false => entry
+            .media
+            .iter()
+            .map(|(file, content_type)| {
+                let src = format!("../media/{}", escape(file));
+                match content_type.split('/').next() {
+                    Some("video") => format!("<video controls src=\"{src}\"></video>"),
+                    Some("audio") => format!("<audio controls src=\"{src}\"></audio>"),
+                    Some("image") => format!("<img src=\"{src}\" alt=\"\">"),
+                    Some("text") => format!("<iframe sandbox src=\"{src}\"></iframe>"),
+                    _ => String::new(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    let mut meta = vec![
+        ("Original", original_link(link)),
+        ("Platform", escape(&entry.platform)),
+    ];
+    if let Some(posted_at) = payload["posted_at"].as_u64() {
+        meta.push(("Posted", calendar::utc_date(posted_at)));
+    }
+    if let Some(added_at) = payload["added_at"].as_u64() {
+        meta.push(("Archived", calendar::utc_date(added_at)));
+    }
+    if !entry.tags.is_empty() {
+        let tags = entry
+            .tags
+            .iter()
+            .map(|t| format!("<a href=\"../tags/{}.html\">{}</a>", slug(t), escape(t)))
+            .collect::<Vec<_>>();
+        meta.push(("Tags", tags.join(", ")));
+    }
+    if let Some(collection) = payload["collection"].as_str() {
+        meta.push(("Collection", escape(collection)));
+    }
+    meta.push(("Visibility", visibility(payload).to_string()));
+    if let Some(cid) = payload["cid"].as_str() {
+        meta.push(("CID", escape(cid)));
+    }
+    if !entry.media.is_empty() {
+        let downloads = entry
+            .media
+            .iter()
+            .map(|(file, _)| format!("<a href=\"../media/{0}\" download>{0}</a>", escape(file)))
+            .collect::<Vec<_>>();
+        meta.push(("Download", downloads.join("<br>")));
+    }
+
+    let meta: String = meta
+        .into_iter()
+        .map(|(name, value)| format!("<dt>{name}</dt><dd>{value}</dd>\n"))
+        .collect();
+    let description = payload["description"].as_str().unwrap_or_default().trim();
+    let body = format!(
+        "{media}\n<pre>{}</pre>\n<dl>\n{meta}</dl>\n",
+        escape(description)
+    );
+    page(&entry.title, "../", &body)
+}
+
+/// Lists entries, linking to their pages relative to `root`.
+fn entry_list<'a>(entries: impl Iterator<Item = &'a SiteEntry>, root: &str) -> String {
+    let mut list = String::from("<ul class=\"entries\">\n");
+    for entry in entries {
+        let date = entry.date.map(calendar::utc_date).unwrap_or_default();
+        list.push_str(&format!(
+            "<li><a href=\"{root}entries/{}.html\">{}</a> <span class=\"meta\">{date} {}</span></li>\n",
+            entry.id,
+            escape(&entry.title),
+            escape(&entry.platform),
+        ));
+    }
+    list.push_str("</ul>\n");
+    list
+}
+
+fn page(title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{title}</title>
+<link rel=\"stylesheet\" href=\"{root}style.css\">
+</head>
+<body>
+<nav><a href=\"{root}index.html\">All</a><a href=\"{root}dates.html\">By date</a><a href=\"{root}platforms.html\">By platform</a><a href=\"{root}tags.html\">By tag</a></nav>
+<h1>{title}</h1>
+{body}</body>
+</html>
+",
+        title = escape(title)
+    )
+}
+
+/// Entries archived before visibility was recorded are visible to the team, see `ApiUser::can_see`.
+pub(crate) fn visibility(payload: &Value) -> Visibility {
+    match payload.get("visibility") {
+        Some(v) => serde_json::from_value(v.clone()).unwrap_or(Visibility::Private),
+        None => Visibility::Team,
+    }
+}
+
+/// The first line of the description, shortened, or the link if there's no description.
+pub(crate) fn title(description: &str, link: &str) -> String {
+    let line = description.lines().next().unwrap_or_default().trim();
+    match line.chars().count() {
+        0 => link.to_string(),
+        1..=100 => line.to_string(),
+        _ => format!("{}…", line.chars().take(100).collect::<String>()),
+    }
+}
+
+/// The platform of a link, named by its domain without common subdomains.
+pub fn platform(link: &str) -> String {
+    let Some(host) = reqwest::Url::parse(link)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    else {
+        return "unknown".to_string();
+    };
+
+    let host = ["www.", "m.", "mobile.", "old.", "music."]
+        .iter()
+        .fold(host.as_str(), |host, prefix| {
+            host.strip_prefix(prefix).unwrap_or(host)
+        });
+    match host {
+        "youtu.be" => "youtube.com".to_string(),
+        "x.com" => "twitter.com".to_string(),
+        "redd.it" => "reddit.com".to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// A file name safe version of a key
+fn slug(key: &str) -> String {
+    key.chars()
+        .map(|c| match c.is_alphanumeric() || c == '.' {
+            true => c.to_ascii_lowercase(),
+            false => '-',
+        })
+        .collect()
+}
+
+/// The original link of an entry as HTML, only linked if it's an http(s) URL so links like `javascript:`
+/// from imported files can't run scripts when clicked.
+pub(crate) fn original_link(link: &str) -> String {
+    let scheme = link.split_once(':').map(|(scheme, _)| scheme.trim());
+    match scheme {
+        Some(scheme)
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            format!("<a href=\"{0}\">{0}</a>", escape(link))
+        }
+        _ => escape(link),
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_only_http_originals() {
+        assert_eq!(
+            original_link("https://example.com/?a=1&b=2"),
+            "<a href=\"https://example.com/?a=1&amp;b=2\">https://example.com/?a=1&amp;b=2</a>"
+        );
+        assert!(original_link("HTTP://example.com").starts_with("<a "));
+        assert_eq!(
+            original_link("javascript:alert(\"x\")"),
+            "javascript:alert(&quot;x&quot;)"
+        );
+        assert_eq!(
+            original_link(" javascript:alert(1)"),
+            " javascript:alert(1)"
+        );
+        assert_eq!(
+            original_link("data:text/html,<b>"),
+            "data:text/html,&lt;b&gt;"
+        );
+        assert_eq!(original_link("example.com"), "example.com");
+    }
+}
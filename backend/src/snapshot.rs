@@ -0,0 +1,416 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::{Captures, Regex};
+use reqwest::{header, Client, Response, Url};
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+    config,
+    download::SiteOptions,
+    import,
+    network::{Component, Network},
+};
+
+/// Sent with snapshot requests, some sites refuse clients that don't look like a browser
+pub(crate) const USER_AGENT: &str = "Mozilla/5.0 (compatible; socialmediaarchive)";
+
+/// The most characters of text extracted from a page
+const MAX_TEXT_CHARS: usize = 100_000;
+
+/// The most bytes of HTML read from a page
+const MAX_PAGE_BYTES: usize = 10_000_000;
+
+/// Captures web pages as single HTML files and extracts their readable text, for links
+/// the media downloaders don't support, like articles, blog posts and Mastodon toots.
+/// Clones are referenced counted.
+#[derive(Debug, Clone)]
+pub struct Snapshotter {
+    network: Network,
+    /// The most bytes of stylesheets and images inlined into a snapshot
+    max_inline_bytes: usize,
+    /// The longest fetching the page may take, and the longest fetching all of its resources may take
+    timeout: Duration,
+}
+
+/// A captured page
+#[derive(Debug)]
+pub struct Snapshot {
+    /// The HTML file with scripts removed and stylesheets and images inlined
+    pub file: PathBuf,
+    /// The readable text of the page
+    pub text: String,
+}
+
+impl Snapshotter {
+    /// Reads `SNAPSHOT_MAX_INLINE_BYTES` (default 20MB) and `SNAPSHOT_TIMEOUT_SECS` (default 60).
+    pub fn new(network: &Network) -> Result<Self> {
+        Ok(Self {
+            network: network.clone(),
+            max_inline_bytes: config::var_or("SNAPSHOT_MAX_INLINE_BYTES", 20_000_000)?,
+            timeout: Duration::from_secs(config::var_or("SNAPSHOT_TIMEOUT_SECS", 60)?),
+        })
+    }
+
+    /// Captures the HTML page at the URL into `snapshot.html` in the directory,
+    /// logged in with the site's cookies or credentials if configured.
+    pub async fn capture(&self, url: &str, dir: &Path, options: &SiteOptions) -> Result<Snapshot> {
+        let url = Url::parse(url).context("invalid link")?;
+        let client = self
+            .network
+            .client_builder(Component::Download, options.proxy.as_deref())?
+            .user_agent(USER_AGENT)
+            .timeout(self.timeout)
+            .build()?;
+        let resp = options
+            .authorize(client.get(url.clone()), &url)?
+            .send()
+            .await
+            .context("failed to fetch page")?
+            .error_for_status()
+            .context("page returned an error")?;
+        let content_type = resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        ensure!(
+            content_type.starts_with("text/html") || content_type.starts_with("application/xhtml"),
+            "link isn't a web page but {content_type}"
+        );
+        // redirects are followed, relative links are relative to where they ended
+        let base = resp.url().clone();
+        let html = read_body(resp, MAX_PAGE_BYTES)
+            .await
+            .context("failed to read page")?
+            .with_context(|| format!("page is larger than {MAX_PAGE_BYTES} bytes"))?;
+        let html = String::from_utf8_lossy(&html);
+
+        let text = extract_text(&html);
+        let snapshot = self.single_file(&client, &html, &base).await;
+        let file = dir.join("snapshot.html");
+        std::fs::write(&file, snapshot).context("failed to write snapshot")?;
+
+        Ok(Snapshot { file, text })
+    }
+
+    /// Turns the page into a single file: scripts are removed, stylesheets and images are inlined
+    /// while they fit in the budget and time allows, and everything else is linked relative to the original URL.
+    async fn single_file(&self, client: &Client, html: &str, base: &Url) -> String {
+        static STYLESHEET: OnceLock<Regex> = OnceLock::new();
+        static IMAGE: OnceLock<Regex> = OnceLock::new();
+        static HEAD: OnceLock<Regex> = OnceLock::new();
+        let stylesheet = STYLESHEET.get_or_init(|| {
+            Regex::new(r#"(?is)<link\b[^>]*\brel\s*=\s*["']?stylesheet\b[^>]*>"#).unwrap()
+        });
+        let image = IMAGE.get_or_init(|| {
+            Regex::new(r#"(?is)(<img\b[^>]*?\bsrc\s*=\s*)["']([^"']+)["']"#).unwrap()
+        });
+        let head = HEAD.get_or_init(|| Regex::new(r"(?i)<head\b[^>]*>").unwrap());
+
+        let html = remove_elements(html, &["script", "noscript"]);
+        let mut budget = self.max_inline_bytes;
+        let deadline = Instant::now() + self.timeout;
+
+        let mut styles = vec![];
+        for link in stylesheet.find_iter(&html) {
+            let css = match attribute(link.as_str(), "href") {
+                Some(href) => fetch_until(deadline, client, base, &href, &mut budget).await,
+                None => None,
+            };
+            styles.push(css.map(|(_, css)| String::from_utf8_lossy(&css).to_string()));
+        }
+        let mut styles = styles.into_iter();
+        let html = stylesheet.replace_all(&html, |link: &Captures| match styles.next().flatten() {
+            Some(css) => format!("<style>{}</style>", css.replace("</style", "<\\/style")),
+            None => link[0].to_string(),
+        });
+
+        let mut images = vec![];
+        for src in image.captures_iter(&html) {
+            let src = import::unescape_html(&src[2]);
+            images.push(fetch_until(deadline, client, base, &src, &mut budget).await);
+        }
+        let mut images = images.into_iter();
+        let html = image.replace_all(&html, |img: &Captures| match images.next().flatten() {
+            Some((content_type, data)) => {
+                format!(
+                    "{}\"data:{content_type};base64,{}\"",
+                    &img[1],
+                    STANDARD.encode(data)
+                )
+            }
+            None => img[0].to_string(),
+        });
+
+        let base_tag = format!("<base href=\"{}\">", base.as_str().replace('"', "%22"));
+        let html = match head.find(&html) {
+            Some(tag) => format!("{}{base_tag}{}", &html[..tag.end()], &html[tag.end()..]),
+            None => format!("{base_tag}{html}"),
+        };
+        match html.trim_start().get(..9) {
+            Some(doctype) if doctype.eq_ignore_ascii_case("<!doctype") => html,
+            _ => format!("<!DOCTYPE html>\n{html}"),
+        }
+    }
+}
+
+/// Fetches a resource of the page like `fetch` if there's time left until the deadline.
+async fn fetch_until(
+    deadline: Instant,
+    client: &Client,
+    base: &Url,
+    href: &str,
+    budget: &mut usize,
+) -> Option<(String, Vec<u8>)> {
+    if Instant::now() >= deadline {
+        return None;
+    }
+    match tokio::time::timeout_at(deadline, fetch(client, base, href, budget)).await {
+        Ok(resource) => resource,
+        Err(_) => {
+            warn!("ran out of time inlining {href} into snapshot, leaving the remaining resources linked");
+            None
+        }
+    }
+}
+
+/// Fetches a resource of the page if it fits in the remaining budget,
+/// returns its content type and content. Failures only leave the resource linked.
+async fn fetch(
+    client: &Client,
+    base: &Url,
+    href: &str,
+    budget: &mut usize,
+) -> Option<(String, Vec<u8>)> {
+    let url = base.join(href).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let resp = match client.get(url.clone()).send().await {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            warn!("couldn't inline {url} into snapshot: {}", resp.status());
+            return None;
+        }
+        Err(e) => {
+            warn!("couldn't inline {url} into snapshot: {e}");
+            return None;
+        }
+    };
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let data = match read_body(resp, *budget).await {
+        Ok(data) => data?,
+        Err(e) => {
+            warn!("couldn't inline {url} into snapshot: {e:#}");
+            return None;
+        }
+    };
+    *budget -= data.len();
+    Some((content_type, data))
+}
+
+/// Reads a response body of up to `max` bytes as it arrives, `None` if it's longer.
+async fn read_body(mut resp: Response, max: usize) -> Result<Option<Vec<u8>>> {
+    if resp
+        .content_length()
+        .is_some_and(|length| length > max as u64)
+    {
+        return Ok(None);
+    }
+    let mut body = vec![];
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > max {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body))
+}
+
+/// The readable text of a page: its description and the text of its article or main content,
+/// or else its body, without navigation, scripts and markup.
+pub fn extract_text(html: &str) -> String {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    static DESCRIPTION: OnceLock<Regex> = OnceLock::new();
+    static CONTENT: OnceLock<Regex> = OnceLock::new();
+    static BREAK: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let title = TITLE.get_or_init(|| Regex::new(r"(?is)<title\b[^>]*>(.*?)</title>").unwrap());
+    let description = DESCRIPTION.get_or_init(|| {
+        Regex::new(
+            r#"(?is)<meta\b[^>]*\b(?:name|property)\s*=\s*["'](?:og:)?description["'][^>]*>"#,
+        )
+        .unwrap()
+    });
+    let content = CONTENT.get_or_init(|| {
+        Regex::new(r"(?is)<(article|main)\b[^>]*>(.*)</(?:article|main)>").unwrap()
+    });
+    let line_break = BREAK.get_or_init(|| {
+        Regex::new(r"(?i)<br\b[^>]*>|</(?:p|div|li|h[1-6]|tr|blockquote|pre|section)>").unwrap()
+    });
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+
+    let mut lines = vec![];
+    if let Some(title) = title.captures(html) {
+        lines.push(title[1].to_string());
+    }
+    // pages rendered by scripts, like Mastodon's, often only have their text here
+    if let Some(meta) = description.find(html) {
+        lines.extend(attribute(meta.as_str(), "content"));
+    }
+
+    let html = remove_elements(
+        html,
+        &[
+            "head", "script", "style", "noscript", "template", "svg", "nav", "header", "footer",
+            "aside", "form",
+        ],
+    );
+    let body = match content.captures(&html) {
+        Some(content) => content[2].to_string(),
+        None => html.clone(),
+    };
+    let body = line_break.replace_all(&body, "\n");
+    let body = tag.replace_all(&body, "");
+    lines.extend(body.lines().map(str::to_string));
+
+    let mut seen = HashSet::new();
+    let mut text = String::new();
+    for line in lines {
+        let line = import::unescape_html(&line)
+            .replace("&nbsp;", " ")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !line.is_empty() && seen.insert(line.clone()) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    text.chars().take(MAX_TEXT_CHARS).collect()
+}
+
+/// Removes the elements with the given tag names, along with their content.
+fn remove_elements(html: &str, names: &[&str]) -> String {
+    let mut html = html.to_string();
+    for name in names {
+        let element = Regex::new(&format!(r"(?is)<{name}\b[^>]*>.*?</{name}\s*>")).unwrap();
+        html = element.replace_all(&html, "").to_string();
+    }
+    html
+}
+
+/// The value of an attribute of an HTML tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let attribute = Regex::new(&format!(
+        r#"(?is)\b{name}\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#
+    ))
+    .ok()?;
+    let value = attribute.captures(tag)?;
+    let value = value.get(1).or(value.get(2)).or(value.get(3))?;
+    Some(import::unescape_html(value.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StubServer;
+
+    async fn get(server: &StubServer) -> Response {
+        Client::new().get(&server.url).send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_bodies_within_the_limit() {
+        let body = vec![b'a'; 1000];
+        let server = StubServer::start(vec![
+            StubServer::response("200 OK", &body, false),
+            StubServer::response("200 OK", &body, true),
+        ]);
+        let read = read_body(get(&server).await, 1000).await.unwrap();
+        assert_eq!(read, Some(body.clone()));
+        let read = read_body(get(&server).await, 5000).await.unwrap();
+        assert_eq!(read, Some(body));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_bodies_over_the_limit() {
+        let body = vec![b'a'; 1000];
+        let server = StubServer::start(vec![
+            // without a length the body is cut off while it's read
+            StubServer::response("200 OK", &body, false),
+            StubServer::response("200 OK", &body, true),
+        ]);
+        assert_eq!(read_body(get(&server).await, 999).await.unwrap(), None);
+        assert_eq!(read_body(get(&server).await, 999).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn inlines_resources_within_the_budget() {
+        let server = StubServer::start(vec![
+            StubServer::response("200 OK", b"body {}", false),
+            StubServer::response("200 OK", &[0; 100], false),
+            StubServer::response("404 Not Found", b"", true),
+        ]);
+        let base = Url::parse(&server.url).unwrap();
+        let client = Client::new();
+
+        let mut budget = 50;
+        let css = fetch(&client, &base, "/style.css", &mut budget).await;
+        assert_eq!(css.map(|(_, data)| data), Some(b"body {}".to_vec()));
+        assert_eq!(budget, 43);
+        assert_eq!(fetch(&client, &base, "/image.png", &mut budget).await, None);
+        assert_eq!(budget, 43);
+        assert_eq!(
+            fetch(&client, &base, "/missing.png", &mut budget).await,
+            None
+        );
+        assert_eq!(
+            fetch(&client, &base, "javascript:alert(1)", &mut budget).await,
+            None
+        );
+        assert_eq!(
+            server.requests(),
+            [
+                "GET /style.css HTTP/1.1",
+                "GET /image.png HTTP/1.1",
+                "GET /missing.png HTTP/1.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_inlining_when_time_runs_out() {
+        // accepts connections but never answers
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = Url::parse(&format!("http://{}", silent.local_addr().unwrap())).unwrap();
+        let snapshotter = Snapshotter {
+            network: Network::default(),
+            max_inline_bytes: 1_000_000,
+            timeout: Duration::from_millis(500),
+        };
+        let html = "<html><head></head><body><img src=\"/1.png\"><img src=\"/2.png\"><img src=\"/3.png\"></body></html>";
+
+        let started = Instant::now();
+        let snapshot = snapshotter.single_file(&Client::new(), html, &base).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        for image in ["/1.png", "/2.png", "/3.png"] {
+            assert!(snapshot.contains(&format!("src=\"{image}\"")));
+        }
+    }
+}
@@ -1,9 +1,23 @@
-use std::path::Path;
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    path::Path,
+};
 
+use actix_web::web::Bytes;
 use anyhow::*;
-use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
+use futures::{Stream, TryStreamExt};
+use ipfs_api::{
+    request::{Add, FilesLs},
+    IpfsApi, IpfsClient, TryFromUri,
+};
+use serde_json::Value;
+use tempdir::TempDir;
 use tracing::warn;
 
+/// The IPFS files directory stored files are copied to, so they can be browsed by name
+const FILES_DIR: &str = "/socialmediaarchive";
+
 #[derive(Clone)]
 pub struct StorageClient {
     ipfs: IpfsClient,
@@ -26,7 +40,7 @@ impl StorageClient {
     }
 
     pub async fn init(&mut self) -> Result<()> {
-        let _ = self.ipfs.files_mkdir("/socialmediaarchive", false).await;
+        let _ = self.ipfs.files_mkdir(FILES_DIR, false).await;
         Ok(())
     }
 
@@ -51,7 +65,7 @@ impl StorageClient {
             .files_cp(
                 &format!("/ipfs/{cid}"),
                 &format!(
-                    "/socialmediaarchive/{}",
+                    "{FILES_DIR}/{}",
                     filepath.file_name().unwrap().to_str().unwrap()
                 ),
             )
@@ -62,4 +76,271 @@ impl StorageClient {
 
         Ok(Cid(cid))
     }
+
+    /// Stores a directory as a pinned IPFS directory, returns its CID and the name and CID of each file in it.
+    pub async fn save_dir(&self, dirpath: impl AsRef<Path>) -> Result<(Cid, Vec<(String, Cid)>)> {
+        let dirpath = dirpath.as_ref();
+        let dirname = dirpath
+            .file_name()
+            .context("directory to store has no name")?
+            .to_string_lossy()
+            .to_string();
+
+        // files are listed with the directory name as prefix, the directory itself last
+        let added = self
+            .ipfs
+            .add_path(dirpath)
+            .await
+            .context("failed to add directory to ipfs")?;
+        let mut root = None;
+        let mut files = vec![];
+        for file in added {
+            match file.name.strip_prefix(&format!("{dirname}/")) {
+                Some(name) => files.push((name.to_string(), Cid(file.hash))),
+                None if file.name == dirname => root = Some(file.hash),
+                None => {}
+            }
+        }
+        let root = root.context("ipfs didn't return the CID of the added directory")?;
+        self.ipfs
+            .pin_add(&root, true)
+            .await
+            .context("failed to pin directory to ipfs")?;
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok((Cid(root), files))
+    }
+
+    /// Streams the content with the given CID.
+    pub fn cat(&self, cid: &str) -> impl Stream<Item = Result<Bytes>> {
+        self.ipfs
+            .cat(cid)
+            .map_err(|e| anyhow!("failed to fetch content from ipfs: {e}"))
+    }
+
+    /// Streams `length` bytes of the content with the given CID, starting at `offset`.
+    pub fn cat_range(
+        &self,
+        cid: &str,
+        offset: u64,
+        length: u64,
+    ) -> impl Stream<Item = Result<Bytes>> {
+        self.ipfs
+            .cat_range(cid, offset as usize, length as usize)
+            .map_err(|e| anyhow!("failed to fetch content from ipfs: {e}"))
+    }
+
+    /// The CIDs pinned recursively, which is how files and directories are pinned when they're stored.
+    pub async fn pins(&self) -> Result<HashSet<String>> {
+        let pins = self
+            .ipfs
+            .pin_ls(None, Some("recursive"))
+            .await
+            .context("failed to list pins in ipfs")?;
+        Ok(pins.keys.into_keys().collect())
+    }
+
+    /// Whether the content is pinned, either itself or as part of a pinned directory.
+    pub async fn is_pinned(&self, cid: &str) -> Result<bool> {
+        match self.ipfs.pin_ls(Some(cid), None).await {
+            // ipfs answers with an error for content that isn't pinned
+            Err(ipfs_api::Error::Api(_)) => Ok(false),
+            Err(e) => Err(anyhow!("failed to look up pin in ipfs: {e}")),
+            _ => Ok(true),
+        }
+    }
+
+    /// Pins the content recursively, fetching it from the IPFS network if it isn't stored locally anymore.
+    pub async fn pin(&self, cid: &str) -> Result<()> {
+        self.ipfs
+            .pin_add(cid, true)
+            .await
+            .context("failed to pin content to ipfs")?;
+        Ok(())
+    }
+
+    /// Unpins the content and removes its copies from the archive's IPFS files directory,
+    /// so the next garbage collection of the IPFS node frees it.
+    pub async fn unpin(&self, cid: &str) -> Result<()> {
+        self.ipfs
+            .pin_rm(cid, true)
+            .await
+            .context("failed to unpin content in ipfs")?;
+
+        let files = self
+            .ipfs
+            .files_ls_with_options(FilesLs {
+                path: Some(FILES_DIR),
+                long: Some(true),
+                ..Default::default()
+            })
+            .await
+            .context("failed to list ipfs files")?;
+        for file in files.entries.iter().filter(|file| file.hash == cid) {
+            self.ipfs
+                .files_rm(&format!("{FILES_DIR}/{}", file.name), true)
+                .await
+                .context("failed to remove ipfs file")?;
+        }
+        Ok(())
+    }
+
+    /// Hashes the content with the given CID again, returns the CID it hashes to. Only works for files
+    /// stored with `save_file` or within a directory stored with `save_dir`, which are added with the defaults.
+    pub async fn rehash(&self, cid: &str) -> Result<Cid> {
+        let temp = TempDir::new("socialmediarehash")?;
+        let path = temp.path().join("content");
+        let mut file = std::fs::File::create(&path)?;
+        let mut content = Box::pin(self.cat(cid));
+        while let Some(chunk) = content.try_next().await? {
+            file.write_all(&chunk)?;
+        }
+        file.flush()?;
+
+        let res = self
+            .ipfs
+            .add_with_options(
+                std::fs::File::open(&path)?,
+                Add {
+                    only_hash: Some(true),
+                    pin: Some(false),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("failed to hash content with ipfs")?;
+        Ok(Cid(res.hash))
+    }
+
+    /// The size in bytes of the content with the given CID.
+    pub async fn size(&self, cid: &str) -> Result<u64> {
+        let stat = self
+            .ipfs
+            .files_stat(&format!("/ipfs/{cid}"))
+            .await
+            .context("failed to stat content in ipfs")?;
+        Ok(stat.size)
+    }
+}
+
+/// The CIDs of the stored files an entry's payload references: every `cid` field in it, such as
+/// the main media's, those of its media items, their thumbnails and previews and the sidecars. Each CID is listed once.
+pub fn referenced_cids(payload: &Value) -> Vec<String> {
+    fn collect(value: &Value, cids: &mut Vec<String>) {
+        match value {
+            Value::Array(values) => values.iter().for_each(|v| collect(v, cids)),
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    match (key.as_str(), value) {
+                        ("cid", Value::String(cid)) if !cids.contains(cid) => {
+                            cids.push(cid.clone())
+                        }
+                        _ => collect(value, cids),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut cids = vec![];
+    collect(payload, &mut cids);
+    cids
+}
+
+/// The CIDs an entry's payload pins directly: those of its files and of its sidecar bundles, but not those
+/// of the files within the bundles, which are pinned through their bundle. If a bundle isn't among the `pins`,
+/// like those of entries restored without their bundles, its files are pinned on their own and listed instead.
+/// Each CID is listed once.
+pub fn pinned_cids(payload: &Value, pins: &HashSet<String>) -> Vec<String> {
+    fn collect(value: &Value, pins: &HashSet<String>, cids: &mut Vec<String>) {
+        match value {
+            Value::Array(values) => values.iter().for_each(|v| collect(v, pins, cids)),
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    let cid = match (key.as_str(), value) {
+                        ("cid", Value::String(cid)) => cid,
+                        ("sidecars", Value::Object(sidecars)) => match sidecars.get("bundle") {
+                            Some(Value::String(bundle)) if pins.contains(bundle) => bundle,
+                            Some(Value::String(_)) => {
+                                collect(value, pins, cids);
+                                continue;
+                            }
+                            _ => continue,
+                        },
+                        _ => {
+                            collect(value, pins, cids);
+                            continue;
+                        }
+                    };
+                    if !cids.contains(cid) {
+                        cids.push(cid.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut cids = vec![];
+    collect(payload, pins, &mut cids);
+    cids
+}
+
+/// Guesses the content type of a file from its first bytes, see `sniff_content_type`.
+pub fn sniff_file(path: impl AsRef<Path>) -> Result<(&'static str, &'static str)> {
+    let mut head = Vec::with_capacity(16);
+    std::fs::File::open(path)?.take(16).read_to_end(&mut head)?;
+    Ok(sniff_content_type(&head))
+}
+
+/// The content types `sniff_content_type` recognizes, with a file extension for each
+const CONTENT_TYPES: [(&str, &str); 14] = [
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("audio/wav", "wav"),
+    ("video/webm", "webm"),
+    ("audio/mp4", "m4a"),
+    ("video/mp4", "mp4"),
+    ("audio/ogg", "ogg"),
+    ("audio/flac", "flac"),
+    ("audio/mpeg", "mp3"),
+    ("application/pdf", "pdf"),
+    ("text/html", "html"),
+    ("application/octet-stream", "bin"),
+];
+
+/// A file extension for the content type, `bin` for unknown types.
+pub fn extension_for(content_type: &str) -> &'static str {
+    CONTENT_TYPES
+        .iter()
+        .find(|(t, _)| *t == content_type)
+        .map_or("bin", |(_, extension)| extension)
+}
+
+/// Guesses the content type of a stored file from its first bytes, along with a file extension for it.
+/// Stored files are named by their CID only, so this is all there is to go by.
+pub fn sniff_content_type(head: &[u8]) -> (&'static str, &'static str) {
+    let content_type = match head {
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "audio/wav",
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => "video/webm",
+        [_, _, _, _, b'f', b't', b'y', b'p', b'M', b'4', b'A', ..] => "audio/mp4",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+        [b'I', b'D', b'3', ..] | [0xff, 0xfb | 0xf3 | 0xf2, ..] => "audio/mpeg",
+        [b'%', b'P', b'D', b'F', ..] => "application/pdf",
+        // page snapshots always start with a doctype
+        [b'<', b'!', b'D' | b'd', b'O' | b'o', b'C' | b'c', b'T' | b't', b'Y' | b'y', b'P' | b'p', b'E' | b'e', ..] => {
+            "text/html"
+        }
+        _ => "application/octet-stream",
+    };
+    (content_type, extension_for(content_type))
 }
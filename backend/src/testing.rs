@@ -0,0 +1,113 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::Path,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use anyhow::{bail, ensure, Result};
+use async_trait::async_trait;
+
+use crate::{
+    download::{Download, Downloader, SiteOptions},
+    health::OriginalStatus,
+};
+
+/// Writes the same files for every link instead of downloading anything, and records the options
+/// of each download
+#[derive(Debug, Default)]
+pub struct FakeDownloader {
+    pub name: &'static str,
+    /// The names and contents of the files written
+    pub files: Vec<(&'static str, &'static str)>,
+    /// Fails after writing the files, like a downloader that gave up halfway
+    pub fail: bool,
+    /// The result of link checks, which fail if it's unset
+    pub check: Option<Option<OriginalStatus>>,
+    pub options: Mutex<Vec<SiteOptions>>,
+}
+
+#[async_trait(?Send)]
+impl Downloader for FakeDownloader {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn download(&self, _: &str, dir: &Path, options: &SiteOptions) -> Result<Download> {
+        self.options.lock().unwrap().push(options.clone());
+        let mut media = vec![];
+        for (name, content) in &self.files {
+            let file = dir.join(name);
+            std::fs::write(&file, content)?;
+            media.push(file);
+        }
+        ensure!(!self.fail, "{} failed", self.name);
+
+        Ok(Download {
+            media,
+            ..Default::default()
+        })
+    }
+
+    async fn check(&self, _: &str, _: &SiteOptions) -> Result<Option<OriginalStatus>> {
+        match self.check {
+            Some(status) => Ok(status),
+            None => bail!("{} doesn't support the link", self.name),
+        }
+    }
+}
+
+/// An HTTP server on a local port that answers each connection with the next of its responses,
+/// for testing clients without the network.
+pub struct StubServer {
+    /// The base URL, like `http://127.0.0.1:1234`
+    pub url: String,
+    requests: mpsc::Receiver<String>,
+}
+
+impl StubServer {
+    /// Serves the raw HTTP responses in order, closing each connection after its response.
+    pub fn start(responses: Vec<Vec<u8>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+
+        thread::spawn(move || {
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                // the headers end at the first empty line
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                    line.clear();
+                }
+                let _ = sender.send(request.trim().to_string());
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// A response with the status and body, without a `Content-Length` if `length` is false
+    pub fn response(status: &str, body: &[u8], length: bool) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {status}\r\nConnection: close\r\n");
+        if length {
+            response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        response.push_str("\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// The request lines received so far, like `GET /path HTTP/1.1`
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.try_iter().collect()
+    }
+}
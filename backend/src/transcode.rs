@@ -0,0 +1,206 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::*;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{config, preview, storage};
+
+/// What is done to downloaded audio and video after downloading
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Postprocess {
+    /// Files are kept as downloaded
+    #[default]
+    None,
+    /// Streams are copied into the preferred container, which is fast but keeps the codecs
+    Remux,
+    /// Files not already in a web playable profile are re-encoded to one
+    Transcode,
+}
+
+impl std::str::FromStr for Postprocess {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Postprocess::None),
+            "remux" => Ok(Postprocess::Remux),
+            "transcode" => Ok(Postprocess::Transcode),
+            _ => bail!("unknown postprocessing {s}, expected none, remux or transcode"),
+        }
+    }
+}
+
+/// Which formats are downloaded and how they're converted afterwards.
+/// Routes may replace it for their site, unset fields then take their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FormatPolicy {
+    /// The largest video height preferred, like 1080, unlimited if unset
+    pub max_height: Option<u32>,
+    /// The preferred container, `mp4` or `webm`
+    pub container: String,
+    /// Preferred video codecs in yt-dlp's naming, like `h264`
+    pub video_codec: Option<String>,
+    /// Preferred audio codecs in yt-dlp's naming, like `aac`
+    pub audio_codec: Option<String>,
+    /// Only downloads the audio, for podcasts and music
+    pub audio_only: bool,
+    pub postprocess: Postprocess,
+    /// Keeps the downloaded file along with the converted one
+    pub keep_original: bool,
+}
+
+impl Default for FormatPolicy {
+    fn default() -> Self {
+        Self {
+            max_height: None,
+            container: "mp4".to_string(),
+            video_codec: None,
+            audio_codec: None,
+            audio_only: false,
+            postprocess: Postprocess::None,
+            keep_original: false,
+        }
+    }
+}
+
+impl FormatPolicy {
+    /// Reads `MAX_RESOLUTION` (a video height, unlimited if unset), `PREFERRED_CONTAINER` (default mp4),
+    /// `PREFERRED_VIDEO_CODEC`, `PREFERRED_AUDIO_CODEC`, `AUDIO_ONLY` (default false),
+    /// `POSTPROCESS` (none, remux or transcode, default none) and `KEEP_ORIGINAL` (default false).
+    pub fn from_env() -> Result<Self> {
+        let policy = Self {
+            max_height: config::var_opt("MAX_RESOLUTION")?,
+            container: config::var_or("PREFERRED_CONTAINER", "mp4".to_string())?,
+            video_codec: config::var_opt("PREFERRED_VIDEO_CODEC")?,
+            audio_codec: config::var_opt("PREFERRED_AUDIO_CODEC")?,
+            audio_only: config::var_or("AUDIO_ONLY", false)?,
+            postprocess: config::var_or("POSTPROCESS", Postprocess::None)?,
+            keep_original: config::var_or("KEEP_ORIGINAL", false)?,
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            matches!(self.container.as_str(), "mp4" | "webm"),
+            "unsupported container {}, expected mp4 or webm",
+            self.container
+        );
+        Ok(())
+    }
+
+    /// The yt-dlp options selecting formats by the policy
+    pub fn yt_dlp_args(&self) -> Vec<String> {
+        let audio_extension = match self.container.as_str() {
+            "webm" => "webm",
+            _ => "m4a",
+        };
+
+        // yt-dlp picks the best format by these fields in order, each preferring up to the given value
+        let mut sort = vec![];
+        if let Some(height) = self.max_height.filter(|_| !self.audio_only) {
+            sort.push(format!("res:{height}"));
+        }
+        if let Some(codec) = self.video_codec.as_ref().filter(|_| !self.audio_only) {
+            sort.push(format!("vcodec:{codec}"));
+        }
+        if let Some(codec) = &self.audio_codec {
+            sort.push(format!("acodec:{codec}"));
+        }
+        sort.push(format!("ext:{}:{audio_extension}", self.container));
+
+        let mut args = vec!["--format-sort".to_string(), sort.join(",")];
+        match self.audio_only {
+            true => args.extend(["--format".to_string(), "bestaudio/best".to_string()]),
+            false => args.extend(["--merge-output-format".to_string(), self.container.clone()]),
+        }
+        args
+    }
+
+    /// Converts a downloaded file by the policy. Returns the converted file, or `None` if the file
+    /// isn't audio or video or is already as the policy wants it.
+    pub async fn postprocess(&self, file: &Path) -> Result<Option<PathBuf>> {
+        let (content_type, extension) = storage::sniff_file(file)?;
+        let audio = match content_type.split('/').next() {
+            Some("audio") => true,
+            Some("video") => self.audio_only,
+            _ => return Ok(None),
+        };
+
+        let (extension_wanted, codecs) = match (self.container.as_str(), audio) {
+            ("webm", true) => ("webm", ["", "opus"]),
+            ("webm", false) => ("webm", ["vp9", "opus"]),
+            (_, true) => ("m4a", ["", "aac"]),
+            (_, false) => ("mp4", ["h264", "aac"]),
+        };
+        let output = file.with_extension(format!("web.{extension_wanted}"));
+
+        let mut options = vec![];
+        if audio {
+            options.push("-vn".to_string());
+        }
+        match self.postprocess {
+            Postprocess::None => return Ok(None),
+            Postprocess::Remux if extension == extension_wanted => return Ok(None),
+            Postprocess::Remux => options.extend(["-c".into(), "copy".into()]),
+            Postprocess::Transcode => {
+                if extension == extension_wanted && probe_codecs(file).await? == codecs {
+                    return Ok(None);
+                }
+                let (video, audio_encoder) = match extension_wanted {
+                    "webm" => (["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"], "libopus"),
+                    _ => (
+                        ["-c:v", "libx264", "-preset", "veryfast", "-crf", "23"],
+                        "aac",
+                    ),
+                };
+                if !audio {
+                    options.extend(video.map(str::to_string));
+                    // keeps the aspect ratio, only ever scales down
+                    let height = self.max_height.unwrap_or(u32::MAX);
+                    options.extend(["-vf".into(), format!("scale=-2:'min(ih,{height})'")]);
+                    options.extend(["-pix_fmt".into(), "yuv420p".into()]);
+                }
+                options.extend(["-c:a".into(), audio_encoder.into()]);
+            }
+        }
+        if extension_wanted != "webm" {
+            options.extend(["-movflags".into(), "+faststart".into()]);
+        }
+
+        let options: Vec<&str> = options.iter().map(String::as_str).collect();
+        preview::ffmpeg(file, &options, &output)
+            .await
+            .context("failed to convert download")?;
+        Ok(Some(output))
+    }
+}
+
+/// The codecs of the first video and audio streams of a file, empty for missing streams.
+async fn probe_codecs(file: &Path) -> Result<[String; 2]> {
+    let probe = |stream: &'static str| async move {
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", stream])
+            .args(["-show_entries", "stream=codec_name", "-of", "csv=p=0"])
+            .arg(file)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("failed to run ffprobe command")?;
+        ensure!(
+            output.status.success(),
+            "ffprobe command failed with {}",
+            output.status
+        );
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    Ok([probe("v:0").await?, probe("a:0").await?])
+}
@@ -0,0 +1,809 @@
+use actix_web::{
+    cookie::{time::Duration as CookieDuration, Cookie, SameSite},
+    error::InternalError,
+    get,
+    http::{header, StatusCode},
+    post, web, Error, HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    api::{AddLink, ClientApi, Entry, EntryUpdate, EntryVersion, Visibility},
+    auth::{ApiUser, SESSION_COOKIE},
+    calendar,
+    daemon::{self, Access, Daemon, Task, TaskFilter, TaskInfo},
+    health::OriginalStatus,
+    site::{escape, original_link, platform, title, visibility},
+};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; max-width: 60rem; margin: 0 auto; padding: 1rem; }
+nav { display: flex; gap: 1rem; align-items: center; margin-bottom: 1rem; }
+nav .user { margin-left: auto; color: #666; }
+nav form { margin: 0; }
+.card { border: 1px solid #ddd; border-radius: 4px; padding: 0.75rem; margin: 0.75rem 0; }
+.card h3 { margin: 0.25rem 0; }
+.meta { color: #666; font-size: 0.9rem; }
+.error { color: #b00; }
+video, audio, img { max-width: 100%; max-height: 30rem; }
+iframe { width: 100%; height: 30rem; border: 1px solid #ccc; }
+form.stacked label { display: block; margin: 0.5rem 0; }
+form.stacked input[type=text], form.stacked input[type=password], form.stacked textarea { width: 100%; box-sizing: border-box; }
+textarea { min-height: 6rem; }
+table { border-collapse: collapse; width: 100%; }
+td, th { text-align: left; padding: 0.25rem 0.5rem; border-bottom: 1px solid #eee; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.25rem 1rem; }
+dd { margin: 0; overflow-wrap: anywhere; }
+pre { white-space: pre-wrap; }
+";
+
+/// How long the web UI session cookie lasts
+const SESSION_DAYS: i64 = 30;
+
+/// The error returned to web UI requests without a valid session, redirects to the login page
+pub fn login_required() -> Error {
+    InternalError::from_response("login required", redirect("/login")).into()
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+fn html(status: StatusCode, body: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+/// Renders a page of the web UI, `user` is `None` for pages shown before logging in.
+fn page(title: &str, user: Option<&ApiUser>, body: &str) -> String {
+    let nav = match user {
+        Some(user) => format!(
+            "<nav><a href=\"/\">Search</a><a href=\"/add\">Add</a><a href=\"/tasks\">Tasks</a>\
+             <a href=\"/originals\">Gone originals</a>\
+             <span class=\"user\">{}</span>\
+             <form method=\"post\" action=\"/logout\"><button>Log out</button></form></nav>",
+            escape(&user.name)
+        ),
+        None => String::new(),
+    };
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{title} - Social Media Archive</title>
+<link rel=\"stylesheet\" href=\"/static/style.css\">
+</head>
+<body>
+{nav}
+<h1>{title}</h1>
+{body}</body>
+</html>
+",
+        title = escape(title)
+    )
+}
+
+fn error_page(user: &ApiUser, status: StatusCode, message: &str) -> HttpResponse {
+    let body = format!("<p class=\"error\">{}</p>\n", escape(message));
+    html(status, page("Error", Some(user), &body))
+}
+
+/// A UTC date and time like `2023-11-20 14:05 UTC`
+fn datetime(timestamp: u64) -> String {
+    format!(
+        "{} {:02}:{:02} UTC",
+        calendar::utc_date(timestamp),
+        timestamp % 86400 / 3600,
+        timestamp % 3600 / 60
+    )
+}
+
+/// An element playing or showing the media at `src`, a plain link if the content type is unknown.
+fn media_element(src: &str, content_type: Option<&str>) -> String {
+    match content_type.and_then(|t| t.split('/').next()) {
+        Some("video") => format!("<video controls preload=\"metadata\" src=\"{src}\"></video>"),
+        Some("audio") => format!("<audio controls preload=\"metadata\" src=\"{src}\"></audio>"),
+        Some("image") => format!("<img src=\"{src}\" alt=\"\" loading=\"lazy\">"),
+        Some("text") => format!("<iframe sandbox src=\"{src}\" loading=\"lazy\"></iframe>"),
+        _ => format!("<p><a href=\"{src}\">Open media</a></p>"),
+    }
+}
+
+fn visibility_options(selected: Visibility) -> String {
+    [Visibility::Private, Visibility::Team, Visibility::Public]
+        .into_iter()
+        .map(|v| {
+            let attr = if v == selected { " selected" } else { "" };
+            format!("<option value=\"{v}\"{attr}>{v}</option>")
+        })
+        .collect()
+}
+
+/// Percent-encodes text for use as a URL path segment.
+fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Splits a comma separated list of tags.
+fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn payload_tags(payload: &Value) -> Vec<String> {
+    payload["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t.as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+#[get("/static/style.css")]
+async fn style_sheet() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(STYLE)
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+fn login_form(error: Option<&str>) -> String {
+    let error = error
+        .map(|e| format!("<p class=\"error\">{}</p>\n", escape(e)))
+        .unwrap_or_default();
+    format!(
+        "{error}<form class=\"stacked\" method=\"post\" action=\"/login\">
+<label>API token <input type=\"password\" name=\"token\" autocomplete=\"current-password\" required></label>
+<button>Log in</button>
+</form>
+<p class=\"meta\">Ask an admin for a token, they are created with <code>backend token create</code>.</p>
+"
+    )
+}
+
+#[get("/login")]
+async fn login_page(access: web::Data<Access>) -> HttpResponse {
+    if access.auth_disabled {
+        return redirect("/");
+    }
+    html(StatusCode::OK, page("Log in", None, &login_form(None)))
+}
+
+#[post("/login")]
+async fn login_action(form: web::Form<LoginForm>, access: web::Data<Access>) -> HttpResponse {
+    let secret = form.token.trim();
+    match access.tokens.authenticate(secret) {
+        Ok(Some(_)) => {
+            let cookie = Cookie::build(SESSION_COOKIE, secret.to_string())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .max_age(CookieDuration::days(SESSION_DAYS))
+                .finish();
+            HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/"))
+                .cookie(cookie)
+                .finish()
+        }
+        Ok(None) => html(
+            StatusCode::UNAUTHORIZED,
+            page("Log in", None, &login_form(Some("Unknown API token"))),
+        ),
+        Err(e) => html(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            page("Log in", None, &login_form(Some(&format!("{e:#}")))),
+        ),
+    }
+}
+
+#[post("/logout")]
+async fn logout_action() -> HttpResponse {
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/login"))
+        .cookie(cookie)
+        .finish()
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[get("/")]
+async fn search_page(
+    query: web::Query<SearchQuery>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let q = query.q.trim();
+    let mut body = format!(
+        "<form method=\"get\" action=\"/\">
+<input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Describe what you are looking for\" size=\"50\" autofocus>
+<button>Search</button>
+</form>
+",
+        escape(q)
+    );
+
+    if !q.is_empty() {
+        match daemon.client_for((*user).clone()).search(q).await {
+            Ok(results) if results.0.is_empty() => body.push_str("<p>No results.</p>\n"),
+            Ok(results) => {
+                for result in results.0 {
+                    body.push_str(&entry_card(&result.entry, Some(result.score)));
+                }
+            }
+            Err(e) => return error_page(&user, StatusCode::BAD_GATEWAY, &format!("{e:#}")),
+        }
+    }
+
+    html(StatusCode::OK, page("Search", Some(&user), &body))
+}
+
+fn entry_card(entry: &Entry, score: Option<f32>) -> String {
+    let payload = &entry.payload;
+    let link = payload["original_link"].as_str().unwrap_or_default();
+    let description = payload["description"].as_str().unwrap_or_default().trim();
+
+    let mut meta = vec![escape(&platform(link))];
+    if let Some(date) = payload["posted_at"]
+        .as_u64()
+        .or(payload["added_at"].as_u64())
+    {
+        meta.push(calendar::utc_date(date));
+    }
+    if payload["original_status"] == "gone" {
+        meta.push("original gone".to_string());
+    }
+    if let Some(score) = score {
+        meta.push(format!("score {score:.3}"));
+    }
+
+    // listings show the small generated files, the full media is on the entry's page
+    let files = format!("/entries/{}", escape(&entry.id));
+    let media = match (
+        payload["preview"]["cid"].as_str(),
+        payload["thumbnail"]["cid"].as_str(),
+        payload["cid"].as_str(),
+    ) {
+        (Some(_), Some(_), _) => format!(
+            "<video controls muted loop preload=\"none\" poster=\"{files}/thumbnail\" src=\"{files}/preview\"></video>"
+        ),
+        (Some(_), None, _) => media_element(&format!("{files}/preview"), Some("video/mp4")),
+        (None, Some(_), _) => media_element(&format!("{files}/thumbnail"), Some("image/jpeg")),
+        (None, None, Some(_)) => {
+            media_element(&format!("{files}/media"), payload["content_type"].as_str())
+        }
+        (None, None, None) => String::new(),
+    };
+    format!(
+        "<div class=\"card\">
+{media}
+<h3><a href=\"/entries/{}\">{}</a></h3>
+<p class=\"meta\">{}</p>
+<p>{}</p>
+</div>
+",
+        escape(&entry.id),
+        escape(&title(description, link)),
+        meta.join(" · "),
+        escape(description),
+    )
+}
+
+#[get("/originals")]
+async fn originals_page(user: web::ReqData<ApiUser>, daemon: web::Data<Daemon>) -> HttpResponse {
+    let report = match daemon
+        .client_for((*user).clone())
+        .link_report(OriginalStatus::Gone)
+        .await
+    {
+        Ok(report) => report,
+        Err(e) => return error_page(&user, StatusCode::BAD_GATEWAY, &format!("{e:#}")),
+    };
+
+    let mut body = format!(
+        "<p class=\"meta\">{} available, {} gone, {} failed to check, {} not checked yet</p>\n",
+        report.available, report.gone, report.error, report.unchecked
+    );
+    if report.entries.is_empty() {
+        body.push_str("<p>No originals are gone.</p>\n");
+    }
+    for entry in &report.entries {
+        body.push_str(&entry_card(entry, None));
+    }
+
+    html(StatusCode::OK, page("Gone originals", Some(&user), &body))
+}
+
+#[get("/add")]
+async fn add_page(user: web::ReqData<ApiUser>) -> HttpResponse {
+    let body = format!(
+        "<form class=\"stacked\" method=\"post\" action=\"/add\">
+<label>Link <input type=\"text\" name=\"link\" required></label>
+<label>Description <textarea name=\"description\" required></textarea></label>
+<label>Tags, separated by commas <input type=\"text\" name=\"tags\"></label>
+<label>Collection <input type=\"text\" name=\"collection\"></label>
+<label>Visibility <select name=\"visibility\">{}</select></label>
+<button>Add</button>
+</form>
+",
+        visibility_options(Visibility::default())
+    );
+    html(StatusCode::OK, page("Add a link", Some(&user), &body))
+}
+
+#[derive(Deserialize)]
+struct AddForm {
+    link: String,
+    description: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    collection: String,
+    visibility: Visibility,
+}
+
+#[post("/add")]
+async fn add_action(
+    form: web::Form<AddForm>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let collection = form.collection.trim();
+    let input = AddLink {
+        visibility: form.visibility,
+        tags: parse_tags(&form.tags),
+        collection: (!collection.is_empty()).then(|| collection.to_string()),
+        ..AddLink::new(form.link.trim(), &form.description)
+    };
+
+    let id = daemon
+        .spawn_task(
+            "add",
+            "/add",
+            user.into_inner(),
+            input,
+            |client, input| async move { client.add_link(&input).await },
+        )
+        .await;
+    redirect(&format!("/tasks/{id}"))
+}
+
+#[get("/tasks")]
+async fn tasks_page(user: web::ReqData<ApiUser>, daemon: web::Data<Daemon>) -> HttpResponse {
+    let filter = TaskFilter {
+        owner: (!user.admin).then(|| user.id.clone()),
+        ..Default::default()
+    };
+    let mut tasks = daemon.list_tasks(&filter).await;
+    tasks.reverse();
+
+    let mut body = String::from(
+        "<table>\n<tr><th>Task</th><th>Type</th><th>Status</th><th>Created</th></tr>\n",
+    );
+    for info in &tasks {
+        body.push_str(&format!(
+            "<tr><td><a href=\"/tasks/{0}\">{0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td></tr>\n",
+            info.id,
+            escape(&info.kind),
+            escape(&task_status(&info.task)),
+            datetime(info.created_at),
+        ));
+    }
+    body.push_str("</table>\n");
+    if tasks.is_empty() {
+        body = "<p>No tasks.</p>\n".to_string();
+    }
+
+    html(StatusCode::OK, page("Tasks", Some(&user), &body))
+}
+
+fn task_status(task: &Task) -> String {
+    match task {
+        Task::InProgress {
+            progress: Some(progress),
+            ..
+        } => format!("in progress: {progress}"),
+        Task::InProgress { .. } => "in progress".to_string(),
+        Task::Cancelled => "cancelled".to_string(),
+        Task::Completed { data } if data.get("error").is_some() => "failed".to_string(),
+        Task::Completed { .. } => "completed".to_string(),
+    }
+}
+
+#[get("/tasks/{task_id}")]
+async fn task_page(
+    task_id: web::Path<u32>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let Some(info) = daemon.get_task_for(*task_id, &user).await else {
+        return error_page(&user, StatusCode::NOT_FOUND, "Unknown task");
+    };
+
+    let TaskInfo {
+        id,
+        kind,
+        created_at,
+        updated_at,
+        task,
+        ..
+    } = info;
+    let mut body = format!(
+        "<dl>
+<dt>Type</dt><dd>{}</dd>
+<dt>Status</dt><dd>{}</dd>
+<dt>Created</dt><dd>{}</dd>
+<dt>Updated</dt><dd>{}</dd>
+</dl>
+",
+        escape(&kind),
+        escape(&task_status(&task)),
+        datetime(created_at),
+        datetime(updated_at),
+    );
+
+    match &task {
+        Task::InProgress { .. } => body.push_str(&format!(
+            "<form method=\"post\" action=\"/tasks/{id}/cancel\"><button>Cancel</button></form>\n"
+        )),
+        Task::Completed { data } => match data.get("error").and_then(|e| e.as_str()) {
+            Some(error) => body.push_str(&format!("<p class=\"error\">{}</p>\n", escape(error))),
+            None => match serde_json::from_value::<Entry>(data.clone()) {
+                Ok(entry) => body.push_str(&entry_card(&entry, None)),
+                Err(_) => body.push_str(&format!(
+                    "<pre>{}</pre>\n",
+                    escape(&serde_json::to_string_pretty(data).unwrap_or_default())
+                )),
+            },
+        },
+        Task::Cancelled => {}
+    }
+
+    // server-rendered pages can't listen for task events, so the page reloads until the task finishes
+    let mut page = page(&format!("Task {id}"), Some(&user), &body);
+    if !task.is_finished() {
+        page = page.replacen(
+            "</head>",
+            "<meta http-equiv=\"refresh\" content=\"2\">\n</head>",
+            1,
+        );
+    }
+    html(StatusCode::OK, page)
+}
+
+#[post("/tasks/{task_id}/cancel")]
+async fn cancel_task_action(
+    task_id: web::Path<u32>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    if daemon.get_task_for(*task_id, &user).await.is_none() {
+        return error_page(&user, StatusCode::NOT_FOUND, "Unknown task");
+    }
+    daemon.cancel_task(*task_id).await;
+    redirect(&format!("/tasks/{task_id}"))
+}
+
+#[get("/entries/{entry_id}")]
+async fn entry_page(
+    entry_id: web::Path<String>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let client = daemon.client_for((*user).clone());
+    let entry = match client.get_entry(&entry_id).await {
+        Ok(entry) => entry,
+        Err(e) => return error_page(&user, StatusCode::NOT_FOUND, &format!("{e:#}")),
+    };
+    let payload = &entry.payload;
+    let link = payload["original_link"].as_str().unwrap_or_default();
+    let description = payload["description"].as_str().unwrap_or_default().trim();
+    let tags = payload_tags(payload);
+    let id = escape(&entry.id);
+
+    // entries archived before media lists only have their main media
+    let media = match (payload["media"].as_array(), payload["cid"].as_str()) {
+        (Some(items), _) if !items.is_empty() => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                media_element(
+                    &format!("/entries/{id}/media/{i}"),
+                    item["content_type"].as_str(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (_, Some(_)) => media_element(
+            &format!("/entries/{id}/media"),
+            payload["content_type"].as_str(),
+        ),
+        _ => "<p>This entry has no media.</p>".to_string(),
+    };
+
+    let mut meta = vec![
+        ("Original", original_link(link)),
+        ("Platform", escape(&platform(link))),
+        ("Visibility", visibility(payload).to_string()),
+        (
+            "Owner",
+            escape(payload["added_by"].as_str().unwrap_or("unknown")),
+        ),
+    ];
+    if let Some(posted_at) = payload["posted_at"].as_u64() {
+        meta.push(("Posted", datetime(posted_at)));
+    }
+    if let Some(added_at) = payload["added_at"].as_u64() {
+        meta.push(("Archived", datetime(added_at)));
+    }
+    if let (Some(status), Some(checked)) = (
+        payload["original_status"].as_str(),
+        payload["last_checked"].as_u64(),
+    ) {
+        meta.push((
+            "Original status",
+            format!("{}, checked {}", escape(status), datetime(checked)),
+        ));
+    }
+    if !tags.is_empty() {
+        meta.push(("Tags", escape(&tags.join(", "))));
+    }
+    if let Some(collection) = payload["collection"].as_str() {
+        meta.push(("Collection", escape(collection)));
+    }
+    if let Some(cid) = payload["cid"].as_str() {
+        meta.push(("CID", escape(cid)));
+    }
+    if payload["media"][0]["original"]["cid"].is_string() {
+        meta.push((
+            "Original file",
+            format!("<a href=\"/entries/{id}/original?download\">Download</a>"),
+        ));
+    }
+    if let Some(items) = payload["media"].as_array().filter(|items| items.len() > 1) {
+        meta.push(("Media items", items.len().to_string()));
+    }
+    let versions = EntryVersion::of_payload(payload);
+    if versions.len() > 1 {
+        let versions = versions
+            .iter()
+            .map(|v| {
+                let archived_at = v.archived_at.map(datetime).unwrap_or_default();
+                match v.current {
+                    true => format!("{} (current) {archived_at}", v.version),
+                    false => format!(
+                        "<a href=\"/entries/{id}/versions/{0}/media\">{0}</a> {archived_at}",
+                        v.version
+                    ),
+                }
+            })
+            .collect::<Vec<_>>();
+        meta.push(("Versions", versions.join("<br>")));
+    }
+    if let Some(files) = payload["sidecars"]["files"].as_array() {
+        let sidecars = files
+            .iter()
+            .filter_map(|f| Some((f["name"].as_str()?, f["kind"].as_str().unwrap_or("other"))))
+            .map(|(name, kind)| {
+                format!(
+                    "<a href=\"/entries/{id}/sidecars/{}\">{}</a> ({kind})",
+                    escape(&url_encode(name)),
+                    escape(name)
+                )
+            })
+            .collect::<Vec<_>>();
+        meta.push(("Sidecars", sidecars.join("<br>")));
+    }
+    let meta: String = meta
+        .into_iter()
+        .map(|(name, value)| format!("<dt>{name}</dt><dd>{value}</dd>\n"))
+        .collect();
+
+    let mut body = format!(
+        "{media}\n<pre>{}</pre>\n<dl>\n{meta}</dl>\n",
+        escape(description)
+    );
+    if let Some(text) = payload["text"].as_str() {
+        body.push_str(&format!(
+            "<details><summary>Page text</summary><pre>{}</pre></details>\n",
+            escape(text)
+        ));
+    }
+
+    if user.admin || user.owns(payload) {
+        let owner = match user.admin {
+            true => format!(
+                "<label>Owner token ID <input type=\"text\" name=\"owner\" value=\"{}\"></label>\n",
+                escape(payload["added_by"].as_str().unwrap_or_default())
+            ),
+            false => String::new(),
+        };
+        body.push_str(&format!(
+            "<h2>Edit</h2>
+<form class=\"stacked\" method=\"post\" action=\"/entries/{id}\">
+<label>Description <textarea name=\"description\">{}</textarea></label>
+<label>Tags, separated by commas <input type=\"text\" name=\"tags\" value=\"{}\"></label>
+<label>Collection <input type=\"text\" name=\"collection\" value=\"{}\"></label>
+<label>Visibility <select name=\"visibility\">{}</select></label>
+{owner}<button>Save</button>
+</form>
+<h2>Refresh</h2>
+<p class=\"meta\">Downloads the link again, and keeps the current content as a version if it changed.</p>
+<form method=\"post\" action=\"/entries/{id}/refresh\"><button>Refresh</button></form>
+",
+            escape(description),
+            escape(&tags.join(", ")),
+            escape(payload["collection"].as_str().unwrap_or_default()),
+            visibility_options(visibility(payload)),
+        ));
+    }
+
+    let title = title(description, link);
+    html(StatusCode::OK, page(&title, Some(&user), &body))
+}
+
+#[derive(Deserialize)]
+struct EntryForm {
+    description: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    collection: String,
+    visibility: Visibility,
+    owner: Option<String>,
+}
+
+#[post("/entries/{entry_id}")]
+async fn update_entry_action(
+    entry_id: web::Path<String>,
+    form: web::Form<EntryForm>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let client = daemon.client_for((*user).clone());
+    let entry = match client.get_entry(&entry_id).await {
+        Ok(entry) => entry,
+        Err(e) => return error_page(&user, StatusCode::NOT_FOUND, &format!("{e:#}")),
+    };
+
+    // only send the fields that changed, so an unchanged description isn't embedded again
+    let form = form.into_inner();
+    let payload = &entry.payload;
+    let changed = |field: &str, value: &str| {
+        (payload[field].as_str().unwrap_or_default().trim() != value.trim())
+            .then(|| value.trim().to_string())
+    };
+    let tags = parse_tags(&form.tags);
+    let update = EntryUpdate {
+        description: changed("description", &form.description),
+        tags: (tags != payload_tags(payload)).then_some(tags),
+        collection: changed("collection", &form.collection),
+        visibility: (form.visibility != visibility(payload)).then_some(form.visibility),
+        added_by: form
+            .owner
+            .as_deref()
+            .and_then(|owner| changed("added_by", owner))
+            .filter(|owner| !owner.is_empty()),
+    };
+
+    match client.update_entry(&entry_id, &update).await {
+        Ok(_) => redirect(&format!("/entries/{}", entry_id.as_str())),
+        Err(e) => error_page(&user, StatusCode::BAD_REQUEST, &format!("{e:#}")),
+    }
+}
+
+#[post("/entries/{entry_id}/refresh")]
+async fn refresh_entry_action(
+    entry_id: web::Path<String>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+) -> HttpResponse {
+    let entry_id = entry_id.into_inner();
+    let id = daemon
+        .spawn_task(
+            "refresh_entry",
+            &format!("/entries/{entry_id}/refresh"),
+            user.into_inner(),
+            entry_id,
+            |client, id| async move { client.refresh_entry(&id).await },
+        )
+        .await;
+    redirect(&format!("/tasks/{id}"))
+}
+
+#[get("/entries/{entry_id}/{file}")]
+async fn media_endpoint(
+    path: web::Path<(String, String)>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (entry_id, file) = path.into_inner();
+    daemon::media_response(
+        &daemon.client_for(user.into_inner()),
+        &entry_id,
+        &file,
+        &req,
+    )
+    .await
+}
+
+#[get("/entries/{entry_id}/media/{index}")]
+async fn media_item_endpoint(
+    path: web::Path<(String, usize)>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (entry_id, index) = path.into_inner();
+    let file = format!("media/{index}");
+    daemon::media_response(
+        &daemon.client_for(user.into_inner()),
+        &entry_id,
+        &file,
+        &req,
+    )
+    .await
+}
+
+#[get("/entries/{entry_id}/sidecars/{name}")]
+async fn sidecar_endpoint(
+    path: web::Path<(String, String)>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (entry_id, name) = path.into_inner();
+    let file = format!("sidecars/{name}");
+    daemon::media_response(
+        &daemon.client_for(user.into_inner()),
+        &entry_id,
+        &file,
+        &req,
+    )
+    .await
+}
+
+#[get("/entries/{entry_id}/versions/{version}/{file:.*}")]
+async fn version_file_endpoint(
+    path: web::Path<(String, usize, String)>,
+    user: web::ReqData<ApiUser>,
+    daemon: web::Data<Daemon>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (entry_id, version, file) = path.into_inner();
+    let file = format!("versions/{version}/{file}");
+    daemon::media_response(
+        &daemon.client_for(user.into_inner()),
+        &entry_id,
+        &file,
+        &req,
+    )
+    .await
+}
@@ -4,13 +4,24 @@ use anyhow::{Context, Result};
 use qdrant_client::{
     prelude::*,
     qdrant::{
-        point_id::PointIdOptions, vectors_config::Config, with_payload_selector::SelectorOptions,
-        RecommendPoints, VectorParams, VectorsConfig, WithPayloadSelector,
+        point_id::PointIdOptions, vectors::VectorsOptions, vectors_config::Config,
+        with_payload_selector::SelectorOptions, Condition, Filter, PointId, RecommendPoints,
+        RetrievedPoint, ScrollPoints, VectorParams, VectorsConfig, WithPayloadSelector,
     },
 };
-use serde_json::{from_value, to_value};
+use serde_json::{from_value, to_value, Value};
 
-use crate::api::*;
+use crate::{api::*, auth::ApiUser};
+
+/// How many entries `VectorDbClient::all` fetches per request
+const SCROLL_PAGE: u32 = 256;
+
+/// An entry along with its embedding vector, if requested
+#[derive(Debug, Clone)]
+pub struct StoredEntry {
+    pub entry: Entry,
+    pub vector: Option<Vec<f32>>,
+}
 
 #[derive(Clone)]
 pub struct VectorDbClient {
@@ -63,24 +74,99 @@ impl VectorDbClient {
         payload: serde_json::Value,
     ) -> Result<String> {
         let id = uuid::Uuid::new_v4().to_string();
+        self.upsert(&id, vector, payload).await?;
+        Ok(id)
+    }
+
+    /// Inserts an entry with the given ID, replacing any entry with the same ID.
+    pub async fn upsert(&self, id: &str, vector: Vec<f32>, payload: Value) -> Result<()> {
         self.client
             .upsert_points(
                 "my_collection",
-                vec![PointStruct::new(id.clone(), vector, from_value(payload)?)],
+                vec![PointStruct::new(
+                    string_to_point_id(id),
+                    vector,
+                    from_value(payload)?,
+                )],
                 None,
             )
             .await
             .context("inserting vector into db failed")?;
-        Ok(id)
+        Ok(())
+    }
+
+    /// Fetches every entry, along with its vector if `with_vectors` is set.
+    pub async fn all(&self, with_vectors: bool) -> Result<Vec<StoredEntry>> {
+        let mut entries = vec![];
+        let mut offset = None;
+        loop {
+            let res = self
+                .client
+                .scroll(&ScrollPoints {
+                    collection_name: "my_collection".to_string(),
+                    offset,
+                    limit: Some(SCROLL_PAGE),
+                    with_payload: Some(true.into()),
+                    with_vectors: Some(with_vectors.into()),
+                    ..Default::default()
+                })
+                .await
+                .context("failed to scroll qdrant")?;
+
+            entries.extend(res.result.into_iter().map(stored_entry));
+            match res.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    /// Fetches the entry with the given ID, `None` if it doesn't exist.
+    pub async fn get(&self, id: &str) -> Result<Option<Entry>> {
+        let res = self
+            .client
+            .get_points(
+                "my_collection",
+                &[id.to_string().into()],
+                Some(false),
+                Some(true),
+                None,
+            )
+            .await
+            .context("failed to get point from qdrant")?;
+
+        Ok(res.result.into_iter().next().map(|x| Entry {
+            id: point_id_to_string(x.id.unwrap()),
+            payload: to_value(x.payload).unwrap(),
+        }))
     }
 
-    pub async fn search(&self, embeddings: Vec<f32>) -> Result<SearchResult> {
+    /// Sets the given fields of an entry's payload, leaving other fields as is.
+    pub async fn set_payload(&self, id: &str, fields: Value) -> Result<()> {
+        self.client
+            .set_payload(
+                "my_collection",
+                &vec![PointId::from(id.to_string())].into(),
+                from_value(fields)?,
+                None,
+            )
+            .await
+            .context("failed to set payload in qdrant")?;
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        embeddings: Vec<f32>,
+        filter: Option<Filter>,
+    ) -> Result<SearchResult> {
         let res = self
             .client
             .recommend(&RecommendPoints {
                 collection_name: "my_collection".to_string(),
                 limit: 100,
                 positive_vectors: vec![embeddings.into()],
+                filter,
                 with_payload: Some(WithPayloadSelector {
                     selector_options: Some(SelectorOptions::Enable(true)),
                 }),
@@ -95,10 +181,7 @@ impl VectorDbClient {
                 .map(|x| SearchEntry {
                     score: x.score,
                     entry: Entry {
-                        id: match x.id.unwrap().point_id_options.unwrap() {
-                            PointIdOptions::Num(n) => n.to_string(),
-                            PointIdOptions::Uuid(n) => n,
-                        },
+                        id: point_id_to_string(x.id.unwrap()),
                         payload: to_value(x.payload).unwrap(),
                     },
                 })
@@ -106,3 +189,51 @@ impl VectorDbClient {
         ))
     }
 }
+
+fn stored_entry(point: RetrievedPoint) -> StoredEntry {
+    let vector = point
+        .vectors
+        .and_then(|v| v.vectors_options)
+        .and_then(|v| match v {
+            VectorsOptions::Vector(v) => Some(v.data),
+            VectorsOptions::Vectors(_) => None,
+        });
+    StoredEntry {
+        entry: Entry {
+            id: point_id_to_string(point.id.unwrap()),
+            payload: to_value(point.payload).unwrap(),
+        },
+        vector,
+    }
+}
+
+/// The inverse of `point_id_to_string`
+fn string_to_point_id(id: &str) -> PointId {
+    match id.parse::<u64>() {
+        Ok(n) => n.into(),
+        Err(_) => id.to_string().into(),
+    }
+}
+
+fn point_id_to_string(id: PointId) -> String {
+    match id.point_id_options.unwrap() {
+        PointIdOptions::Num(n) => n.to_string(),
+        PointIdOptions::Uuid(n) => n,
+    }
+}
+
+/// The filter for entries the user may see (see `ApiUser::can_see`), `None` if they may see all entries.
+pub fn visible_to(user: &ApiUser) -> Option<Filter> {
+    if user.admin {
+        return None;
+    }
+
+    Some(Filter::should([
+        Condition::matches(
+            "visibility",
+            vec![Visibility::Team.to_string(), Visibility::Public.to_string()],
+        ),
+        Condition::is_empty("visibility"),
+        Condition::matches("added_by", user.id.clone()),
+    ]))
+}